@@ -0,0 +1,13 @@
+/// Aggregated top of the book, best levels first on each side
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BookDepth {
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthLevel {
+    pub price: u64,
+    pub qty: u64, // Visible quantity, iceberg reserve is not shown
+    pub order_count: u64,
+}
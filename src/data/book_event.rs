@@ -1,10 +1,37 @@
-use crate::data::{order_types::IncomingSide, orders::resting_orders::OrderId};
+use std::fmt;
+
+use crate::data::{
+    book_snapshot::BookSnapshot,
+    order_types::{IncomingSide, StpMode, SymbolId, TimeInForce},
+    orders::resting_orders::{AccountId, OrderId},
+};
+
+/// Journal record wrapping every event the engine emits
+///
+/// `seq` orders all events globally and has no gaps, so a consumer can detect missing records.
+/// `input_seq` and `batch_index` tie the event back to the input line that produced it.
+pub struct EventEnvelope {
+    pub seq: u64,         // Engine sequence number, starts at 1
+    pub input_seq: u64,   // Input that caused the event, starts at 1 (0 = not caused by an input)
+    pub batch_index: u32, // Position within the events of that input
+    pub symbol: SymbolId, // Market whose book produced the event
+    pub event: BookEvent,
+}
 
 pub enum BookEvent {
     Match(MatchEvent),
     Cancel(CancelEvent),
     Insert(InsertEvent),
-    BookSnapshot(String),
+    Amend(AmendEvent),
+    Replenish(ReplenishEvent),
+    Reject(RejectEvent),
+    Expire(ExpireEvent),
+    Kill(KillEvent),
+    BandCancel(BandCancelEvent),
+    StopInsert(StopInsertEvent),
+    Trigger(TriggerEvent),
+    SelfTrade(SelfTradeEvent),
+    BookSnapshot(BookSnapshot),
 }
 
 pub struct MatchEvent {
@@ -26,5 +53,142 @@ pub struct InsertEvent {
     pub price: u64,
     pub side: IncomingSide,
     pub qty: u32,
+    pub hidden_qty: u32,
+    pub ts: i64,
+}
+
+/// Resting order changed in place or cancel-replaced to a new price/size
+pub struct AmendEvent {
+    pub order_id: OrderId,
+    pub old_price: u64,
+    pub old_qty: u32,
+    pub price: u64,
+    pub qty: u32,
+    pub ts: i64,
+}
+
+/// Iceberg slice refilled from reserve and moved to the back of its level
+pub struct ReplenishEvent {
+    pub order_id: OrderId,
+    pub price: u64,
+    pub qty: u32,
+    pub hidden_qty: u32,
+    pub ts: i64,
+}
+
+/// DAY/GTD order removed from the book by the engine clock
+pub struct ExpireEvent {
+    pub order_id: OrderId,
+    pub qty: u32,
+    pub ts: i64,
+}
+
+/// Unfilled quantity of an IOC/FOK order that was dropped instead of resting
+pub struct KillEvent {
+    pub order_id: OrderId,
+    pub qty: u32,
+    pub time_in_force: TimeInForce,
+    pub ts: i64,
+}
+
+/// Unfilled quantity of a market order that reached the edge of the price band
+pub struct BandCancelEvent {
+    pub order_id: OrderId,
+    pub qty: u32,
+    pub band_price: u64, // Last price the order was allowed to trade at
+    pub ts: i64,
+}
+
+/// Stop order parked in the stop book
+pub struct StopInsertEvent {
+    pub order_id: OrderId,
+    pub trigger_price: u64,
+    pub limit_price: Option<u64>,
+    pub side: IncomingSide,
+    pub qty: u32,
     pub ts: i64,
 }
+
+/// Stop order released by the last trade price, followed by its own matching events
+pub struct TriggerEvent {
+    pub order_id: OrderId,
+    pub trigger_price: u64,
+    pub last_price: u64,
+    pub ts: i64,
+}
+
+/// Self-trade prevented instead of a match, quantities are what each side lost
+pub struct SelfTradeEvent {
+    pub maker: OrderId,
+    pub taker: OrderId,
+    pub account_id: AccountId,
+    pub mode: StpMode,
+    pub maker_qty: u32,
+    pub taker_qty: u32,
+    pub ts: i64,
+}
+
+/// Input refused by the engine or the replay reader
+pub struct RejectEvent {
+    pub order_id: Option<OrderId>, // None if the id itself could not be read
+    pub reason: RejectReason,
+    pub ts: i64,
+}
+
+impl BookEvent {
+    #[inline]
+    pub fn reject(order_id: Option<OrderId>, reason: RejectReason, ts: i64) -> Self {
+        BookEvent::Reject(RejectEvent {
+            order_id,
+            reason,
+            ts,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    UnknownOrderId,
+    DuplicateOrderId,
+    ZeroQuantity,
+    ZeroPrice,
+    InvalidSide,
+    NoLiquidity,
+    PostOnlyWouldCross,
+    ExpiredOnArrival,
+    Malformed,      // Replay line that could not be parsed
+    UnknownSymbol,  // No market for the order's symbol
+    PriceNotOnTick, // Price is not a multiple of the tick size
+    QtyNotOnLot,    // Quantity is not a multiple of the lot size
+    QtyBelowMin,
+    QtyAboveMax,
+    PriceBelowMin,
+    PriceAboveMax,
+    PriceOutsideBand,     // Limit price outside the dynamic price band
+    PostOnlyImmediateTif, // Post-only combined with IOC or FOK
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::UnknownOrderId => write!(f, "UNKNOWN_ORDER_ID"),
+            RejectReason::DuplicateOrderId => write!(f, "DUPLICATE_ORDER_ID"),
+            RejectReason::ZeroQuantity => write!(f, "ZERO_QUANTITY"),
+            RejectReason::ZeroPrice => write!(f, "ZERO_PRICE"),
+            RejectReason::InvalidSide => write!(f, "INVALID_SIDE"),
+            RejectReason::NoLiquidity => write!(f, "NO_LIQUIDITY"),
+            RejectReason::PostOnlyWouldCross => write!(f, "POST_ONLY_WOULD_CROSS"),
+            RejectReason::ExpiredOnArrival => write!(f, "EXPIRED_ON_ARRIVAL"),
+            RejectReason::Malformed => write!(f, "MALFORMED"),
+            RejectReason::UnknownSymbol => write!(f, "UNKNOWN_SYMBOL"),
+            RejectReason::PriceNotOnTick => write!(f, "PRICE_NOT_ON_TICK"),
+            RejectReason::QtyNotOnLot => write!(f, "QTY_NOT_ON_LOT"),
+            RejectReason::QtyBelowMin => write!(f, "QTY_BELOW_MIN"),
+            RejectReason::QtyAboveMax => write!(f, "QTY_ABOVE_MAX"),
+            RejectReason::PriceBelowMin => write!(f, "PRICE_BELOW_MIN"),
+            RejectReason::PriceAboveMax => write!(f, "PRICE_ABOVE_MAX"),
+            RejectReason::PriceOutsideBand => write!(f, "PRICE_OUTSIDE_BAND"),
+            RejectReason::PostOnlyImmediateTif => write!(f, "POST_ONLY_IMMEDIATE_TIF"),
+        }
+    }
+}
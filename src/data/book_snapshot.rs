@@ -0,0 +1,29 @@
+use crate::data::order_types::SymbolId;
+use crate::data::orders::resting_orders::{AccountId, OrderId};
+
+/// Full state of the book at one point in time, best levels first on each side
+pub struct BookSnapshot {
+    pub symbol: SymbolId,
+    pub bids: Vec<LevelSnapshot>,
+    pub asks: Vec<LevelSnapshot>,
+    pub checksum: u64,
+    pub checksum_version: u32,
+    pub crc_depth: u32,
+    pub top_levels_crc: u32,
+}
+
+pub struct LevelSnapshot {
+    pub price: u64,
+    pub qty: u64,        // Visible quantity of all orders at the level
+    pub hidden_qty: u64, // Iceberg reserve of all orders at the level
+    pub order_count: u64,
+    pub orders: Option<Vec<OrderSnapshot>>, // FIFO order, only when requested
+}
+
+pub struct OrderSnapshot {
+    pub order_id: OrderId,
+    pub qty: u32,
+    pub hidden_qty: u32,
+    pub account_id: AccountId,
+    pub ts: i64,
+}
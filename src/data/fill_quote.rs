@@ -0,0 +1,27 @@
+/// What a taker order would fill against the book right now, without changing it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FillQuote {
+    pub fills: Vec<QuoteFill>, // One per level swept, best first
+    pub filled_qty: u64,
+    pub notional: u128, // Sum of price * qty over the fills
+    pub worst_price: Option<u64>,
+    pub unfilled_qty: u64, // Left over once the book or the limit price runs out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuoteFill {
+    pub price: u64,
+    pub qty: u64,
+}
+
+impl FillQuote {
+    /// Volume weighted fill price, None if nothing would fill
+    pub fn average_price(&self) -> Option<f64> {
+        (self.filled_qty > 0).then(|| self.notional as f64 / self.filled_qty as f64)
+    }
+
+    #[inline]
+    pub fn is_complete(&self) -> bool {
+        self.unfilled_qty == 0
+    }
+}
@@ -1,4 +1,7 @@
+pub mod book_depth;
 pub mod book_event;
+pub mod book_snapshot;
+pub mod fill_quote;
 pub mod order_types;
 pub mod orders;
 pub mod price_level;
@@ -1,8 +1,17 @@
 use std::fmt;
+use std::str::FromStr;
 
 use crate::data::orders::inbound_orders::{
-    IncomingCancelOrder, IncomingLimitOrder, IncomingMarketOrder,
+    IncomingCancelOrder, IncomingIcebergOrder, IncomingInvalidOrder, IncomingLimitOrder,
+    IncomingMarketOrder, IncomingModifyOrder, IncomingStopOrder, IncomingTimeUpdate,
 };
+use crate::data::orders::resting_orders::OrderId;
+
+/// Instrument an order trades, every symbol has its own book
+pub type SymbolId = u32;
+
+/// The only market of an engine without a symbol registry
+pub const DEFAULT_SYMBOL: SymbolId = 0;
 
 #[repr(u8)]
 #[derive(Debug, Clone, Hash)]
@@ -11,11 +20,92 @@ pub enum IncomingSide {
     Sell = 1,
 }
 
+/// How a post-only order is handled when it would take liquidity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostOnlyMode {
+    Reject, // Reject the order outright
+    Slide,  // Reprice one tick behind the opposite best and rest
+}
+
+/// Action taken when a taker would trade against a resting order of the same account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StpMode {
+    CancelNewest,       // Cancel the taker
+    CancelOldest,       // Cancel the resting maker and keep matching
+    CancelBoth,         // Cancel both sides
+    DecrementAndCancel, // Reduce both by the smaller total size, the smaller one is gone
+}
+
+/// How long a limit order stays live
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeInForce {
+    #[default]
+    Gtc, // Good till cancelled
+    Ioc,      // Immediate or cancel, remainder is killed
+    Fok,      // Fill or kill, all or nothing
+    Day,      // Expires at the end of the engine day
+    Gtd(i64), // Good till date, expiry in microseconds since epoch
+}
+
+impl TimeInForce {
+    /// Expiry timestamp if already known (DAY is resolved by the engine on arrival)
+    #[inline]
+    pub fn expires_at(&self) -> Option<i64> {
+        match self {
+            TimeInForce::Gtd(expires_at) => Some(*expires_at),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum IncomingOrder {
     InboundLimit(IncomingLimitOrder),
     InboundMarket(IncomingMarketOrder),
+    InboundIceberg(IncomingIcebergOrder),
+    InboundStop(IncomingStopOrder),
+    InboundModify(IncomingModifyOrder),
+    InboundInvalid(IncomingInvalidOrder),
     InboundCancel(IncomingCancelOrder),
+    InboundTime(IncomingTimeUpdate),
+}
+
+impl IncomingOrder {
+    /// Timestamp carried by the input, only set for replay `TIME` lines
+    pub fn supplied_ts(&self) -> Option<i64> {
+        match self {
+            IncomingOrder::InboundTime(time) => Some(time.ts),
+            _ => None,
+        }
+    }
+
+    /// Market the input is for, None for inputs that apply to every market (`TIME`)
+    pub fn symbol(&self) -> Option<SymbolId> {
+        match self {
+            IncomingOrder::InboundLimit(order) => Some(order.symbol),
+            IncomingOrder::InboundMarket(order) => Some(order.symbol),
+            IncomingOrder::InboundIceberg(order) => Some(order.symbol),
+            IncomingOrder::InboundStop(order) => Some(order.symbol),
+            IncomingOrder::InboundModify(order) => Some(order.symbol),
+            IncomingOrder::InboundCancel(order) => Some(order.symbol),
+            IncomingOrder::InboundInvalid(order) => Some(order.symbol),
+            IncomingOrder::InboundTime(_) => None,
+        }
+    }
+
+    /// Order id the input refers to, None if it could not be read
+    pub fn order_id(&self) -> Option<OrderId> {
+        match self {
+            IncomingOrder::InboundLimit(order) => Some(order.order_id),
+            IncomingOrder::InboundMarket(order) => Some(order.order_id),
+            IncomingOrder::InboundIceberg(order) => Some(order.order_id),
+            IncomingOrder::InboundStop(order) => Some(order.order_id),
+            IncomingOrder::InboundModify(order) => Some(order.order_id),
+            IncomingOrder::InboundCancel(order) => Some(order.order_id),
+            IncomingOrder::InboundInvalid(order) => order.order_id,
+            IncomingOrder::InboundTime(_) => None,
+        }
+    }
 }
 
 impl fmt::Display for IncomingSide {
@@ -26,3 +116,40 @@ impl fmt::Display for IncomingSide {
         }
     }
 }
+
+impl fmt::Display for TimeInForce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeInForce::Gtc => write!(f, "GTC"),
+            TimeInForce::Ioc => write!(f, "IOC"),
+            TimeInForce::Fok => write!(f, "FOK"),
+            TimeInForce::Day => write!(f, "DAY"),
+            TimeInForce::Gtd(expires_at) => write!(f, "GTD:{}", expires_at),
+        }
+    }
+}
+
+impl fmt::Display for StpMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StpMode::CancelNewest => write!(f, "CANCEL_NEWEST"),
+            StpMode::CancelOldest => write!(f, "CANCEL_OLDEST"),
+            StpMode::CancelBoth => write!(f, "CANCEL_BOTH"),
+            StpMode::DecrementAndCancel => write!(f, "DECREMENT_AND_CANCEL"),
+        }
+    }
+}
+
+impl FromStr for StpMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cancel-newest" => Ok(StpMode::CancelNewest),
+            "cancel-oldest" => Ok(StpMode::CancelOldest),
+            "cancel-both" => Ok(StpMode::CancelBoth),
+            "decrement-and-cancel" => Ok(StpMode::DecrementAndCancel),
+            other => Err(format!("Unknown self-trade prevention mode: {}", other)),
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use crate::data::order_types::IncomingSide;
+use crate::data::book_event::RejectReason;
+use crate::data::order_types::{IncomingSide, PostOnlyMode, SymbolId, TimeInForce};
+use crate::data::orders::resting_orders::{AccountId, OrderId};
 
 #[derive(Debug)]
 pub struct IncomingLimitOrder {
@@ -6,6 +8,10 @@ pub struct IncomingLimitOrder {
     pub price: u64,
     pub qty: u32,
     pub side: IncomingSide,
+    pub account_id: AccountId,
+    pub post_only: Option<PostOnlyMode>, // None for a regular limit order
+    pub time_in_force: TimeInForce,
+    pub symbol: SymbolId,
 }
 
 #[derive(Debug)]
@@ -13,9 +19,62 @@ pub struct IncomingMarketOrder {
     pub order_id: u64,
     pub qty: u32,
     pub side: IncomingSide,
+    pub account_id: AccountId,
+    pub symbol: SymbolId,
+}
+
+/// Limit order that only shows `display_qty` on the book at a time.
+/// The rest of `total_qty` is kept in reserve and replenished as the visible slice fills.
+#[derive(Debug)]
+pub struct IncomingIcebergOrder {
+    pub order_id: u64,
+    pub price: u64,
+    pub display_qty: u32,
+    pub total_qty: u32,
+    pub side: IncomingSide,
+    pub account_id: AccountId,
+    pub symbol: SymbolId,
+}
+
+/// Stop order parked until the last trade price reaches `trigger_price`
+/// Becomes a limit order at `limit_price` if set, otherwise a market order
+#[derive(Debug)]
+pub struct IncomingStopOrder {
+    pub order_id: u64,
+    pub trigger_price: u64,
+    pub limit_price: Option<u64>,
+    pub qty: u32,
+    pub side: IncomingSide,
+    pub account_id: AccountId,
+    pub symbol: SymbolId,
+}
+
+/// Amend a resting order to a new price and total remaining quantity
+#[derive(Debug)]
+pub struct IncomingModifyOrder {
+    pub order_id: u64,
+    pub price: u64,
+    pub qty: u32,
+    pub symbol: SymbolId,
 }
 
 #[derive(Debug)]
 pub struct IncomingCancelOrder {
     pub order_id: u64,
+    pub symbol: SymbolId,
+}
+
+/// Timestamp from the replay file, moves the replay clock forward
+#[derive(Debug)]
+pub struct IncomingTimeUpdate {
+    pub ts: i64,
+}
+
+/// Input line that failed validation before reaching the engine
+/// Carried through so the rejection shows up in the event journal in sequence
+#[derive(Debug)]
+pub struct IncomingInvalidOrder {
+    pub order_id: Option<OrderId>,
+    pub reason: RejectReason,
+    pub symbol: SymbolId,
 }
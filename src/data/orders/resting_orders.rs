@@ -1,21 +1,33 @@
-use chrono::Utc;
-
 use crate::data::order_types::IncomingSide;
-use crate::data::orders::inbound_orders::IncomingLimitOrder;
+use crate::data::orders::inbound_orders::{IncomingIcebergOrder, IncomingLimitOrder};
 
 pub type OrderId = u64;
+pub type AccountId = u64;
 
 #[derive(Debug)]
 pub struct RestingOrder {
     pub order_id: OrderId,
     pub price: u64,
-    pub qty: u32,
+    pub qty: u32, // Visible quantity
     pub side: IncomingSide,
+    pub account_id: AccountId,
+    pub display_qty: Option<u32>, // Slice size for iceberg orders
+    pub hidden_qty: u32,          // Reserve not yet shown on the book
+    pub expires_at: Option<i64>,  // DAY/GTD expiry in microseconds since epoch
+    pub post_only: bool,          // Amends may not take liquidity
     pub prev: Option<usize>,
     pub next: Option<usize>,
     pub ts: i64, // microseconds since epoch
 }
 
+impl RestingOrder {
+    /// Visible plus hidden quantity
+    #[inline]
+    pub fn total_qty(&self) -> u32 {
+        self.qty + self.hidden_qty
+    }
+}
+
 impl From<IncomingLimitOrder> for RestingOrder {
     fn from(order: IncomingLimitOrder) -> Self {
         Self {
@@ -23,9 +35,39 @@ impl From<IncomingLimitOrder> for RestingOrder {
             price: order.price,
             qty: order.qty,
             side: order.side,
+            account_id: order.account_id,
+            display_qty: None,
+            hidden_qty: 0,
+            expires_at: order.time_in_force.expires_at(),
+            post_only: order.post_only.is_some(),
+            prev: None,
+            next: None,
+            ts: 0, // Stamped with engine time on insert
+        }
+    }
+}
+
+impl From<IncomingIcebergOrder> for RestingOrder {
+    fn from(order: IncomingIcebergOrder) -> Self {
+        // Validation rejects icebergs without a display size before they get here
+        debug_assert!(
+            order.display_qty > 0,
+            "iceberg display size must be positive"
+        );
+
+        Self {
+            order_id: order.order_id,
+            price: order.price,
+            qty: order.total_qty,
+            side: order.side,
+            account_id: order.account_id,
+            display_qty: Some(order.display_qty),
+            hidden_qty: 0, // Split into visible/hidden on insert
+            expires_at: None,
+            post_only: false,
             prev: None,
             next: None,
-            ts: Utc::now().timestamp_micros(),
+            ts: 0, // Stamped with engine time on insert
         }
     }
 }
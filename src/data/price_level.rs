@@ -4,4 +4,6 @@ pub struct PriceLevel {
     pub head: Option<usize>,
     pub tail: Option<usize>,
     pub total_orders: u64,
+    pub total_qty: u64,  // Visible qty of all orders at this level
+    pub hidden_qty: u64, // Iceberg reserve behind the visible qty
 }
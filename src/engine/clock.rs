@@ -0,0 +1,75 @@
+use chrono::Utc;
+
+/// Source of engine time, in microseconds
+///
+/// The engine ticks the clock once per input and stamps every event of that input
+/// with the same `now()`. Only the wall clock makes the output depend on when it runs.
+pub trait Clock: Send {
+    /// Advance for the next input. `supplied_ts` is set for timestamps read from the replay file
+    fn tick(&mut self, input_seq: u64, supplied_ts: Option<i64>);
+
+    fn now(&self) -> i64;
+}
+
+/// System time, microseconds since epoch
+#[derive(Default)]
+pub struct WallClock {
+    now: i64,
+}
+
+impl Clock for WallClock {
+    fn tick(&mut self, _input_seq: u64, _supplied_ts: Option<i64>) {
+        self.now = Utc::now().timestamp_micros();
+    }
+
+    #[inline]
+    fn now(&self) -> i64 {
+        self.now
+    }
+}
+
+/// Time is the input sequence number, fully deterministic
+#[derive(Default)]
+pub struct LogicalClock {
+    now: i64,
+}
+
+impl Clock for LogicalClock {
+    fn tick(&mut self, input_seq: u64, _supplied_ts: Option<i64>) {
+        self.now = input_seq as i64;
+    }
+
+    #[inline]
+    fn now(&self) -> i64 {
+        self.now
+    }
+}
+
+/// Time only moves when the replay file says so (`TIME` lines)
+#[derive(Default)]
+pub struct ReplayClock {
+    now: i64,
+}
+
+impl Clock for ReplayClock {
+    fn tick(&mut self, _input_seq: u64, supplied_ts: Option<i64>) {
+        if let Some(ts) = supplied_ts {
+            self.now = self.now.max(ts);
+        }
+    }
+
+    #[inline]
+    fn now(&self) -> i64 {
+        self.now
+    }
+}
+
+/// Clock for a CLI name: wall, logical or replay
+pub fn clock_from_name(name: &str) -> Result<Box<dyn Clock>, String> {
+    match name {
+        "wall" => Ok(Box::new(WallClock::default())),
+        "logical" => Ok(Box::new(LogicalClock::default())),
+        "replay" => Ok(Box::new(ReplayClock::default())),
+        other => Err(format!("Unknown clock: {}", other)),
+    }
+}
@@ -0,0 +1,242 @@
+//! Instrument reference data: the prices and sizes a market accepts
+//!
+//! Loaded from TOML or JSON (by file extension), any field left out keeps its permissive default:
+//!
+//! ```toml
+//! [[instruments]]
+//! symbol = 1
+//! tick_size = 5
+//! lot_size = 10
+//! min_qty = 10
+//! max_qty = 1000000
+//! min_price = 5
+//! max_price = 1000000
+//! ```
+
+use crate::data::book_event::RejectReason;
+use crate::data::order_types::{DEFAULT_SYMBOL, IncomingOrder, SymbolId};
+use crate::storage::codec::invalid_data;
+use serde::Deserialize;
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstrumentSpec {
+    pub symbol: SymbolId,
+    pub tick_size: u64, // Prices must be a multiple of it
+    pub lot_size: u32,  // Quantities must be a multiple of it
+    pub min_qty: u32,
+    pub max_qty: u32,
+    pub min_price: u64,
+    pub max_price: u64,
+}
+
+impl Default for InstrumentSpec {
+    fn default() -> Self {
+        Self {
+            symbol: DEFAULT_SYMBOL,
+            tick_size: 1,
+            lot_size: 1,
+            min_qty: 1,
+            max_qty: u32::MAX,
+            min_price: 1,
+            max_price: u64::MAX,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpecFile {
+    instruments: Vec<InstrumentSpec>,
+}
+
+impl InstrumentSpec {
+    /// Reason a new order or amend breaks the spec, if it does
+    ///
+    /// Amends to zero quantity are cancels and skip the quantity checks. For icebergs the
+    /// total is checked against the size limits and both sizes against the lot.
+    pub fn check(&self, order: &IncomingOrder) -> Option<RejectReason> {
+        match order {
+            IncomingOrder::InboundLimit(order) => self
+                .check_price(order.price)
+                .or_else(|| self.check_qty(order.qty)),
+            IncomingOrder::InboundMarket(order) => self.check_qty(order.qty),
+            IncomingOrder::InboundIceberg(order) => self
+                .check_price(order.price)
+                .or_else(|| self.check_qty(order.total_qty))
+                .or_else(|| self.check_lot(order.display_qty)),
+            IncomingOrder::InboundStop(order) => self
+                .check_price(order.trigger_price)
+                .or_else(|| order.limit_price.and_then(|price| self.check_price(price)))
+                .or_else(|| self.check_qty(order.qty)),
+            IncomingOrder::InboundModify(order) => self
+                .check_price(order.price)
+                .or_else(|| (order.qty > 0).then(|| self.check_qty(order.qty)).flatten()),
+            IncomingOrder::InboundCancel(_)
+            | IncomingOrder::InboundTime(_)
+            | IncomingOrder::InboundInvalid(_) => None,
+        }
+    }
+
+    pub fn check_price(&self, price: u64) -> Option<RejectReason> {
+        if price < self.min_price {
+            Some(RejectReason::PriceBelowMin)
+        } else if price > self.max_price {
+            Some(RejectReason::PriceAboveMax)
+        } else if !price.is_multiple_of(self.tick_size) {
+            Some(RejectReason::PriceNotOnTick)
+        } else {
+            None
+        }
+    }
+
+    pub fn check_qty(&self, qty: u32) -> Option<RejectReason> {
+        if qty < self.min_qty {
+            Some(RejectReason::QtyBelowMin)
+        } else if qty > self.max_qty {
+            Some(RejectReason::QtyAboveMax)
+        } else {
+            self.check_lot(qty)
+        }
+    }
+
+    fn check_lot(&self, qty: u32) -> Option<RejectReason> {
+        (!qty.is_multiple_of(self.lot_size)).then_some(RejectReason::QtyNotOnLot)
+    }
+
+    /// Sizes must be non-zero and every range non-empty
+    fn validate(&self) -> io::Result<()> {
+        let error = if self.tick_size == 0 || self.lot_size == 0 {
+            "tick and lot size must be positive"
+        } else if self.min_qty > self.max_qty {
+            "min_qty is above max_qty"
+        } else if self.min_price > self.max_price {
+            "min_price is above max_price"
+        } else {
+            return Ok(());
+        };
+
+        Err(invalid_data(format!(
+            "Invalid instrument {}: {}",
+            self.symbol, error
+        )))
+    }
+}
+
+/// Read specs from a `.json` file, or TOML for any other extension
+pub fn load_specs(path: &str) -> io::Result<Vec<InstrumentSpec>> {
+    let text = std::fs::read_to_string(path)?;
+    if path.ends_with(".json") {
+        specs_from_json(&text)
+    } else {
+        specs_from_toml(&text)
+    }
+}
+
+pub fn specs_from_toml(text: &str) -> io::Result<Vec<InstrumentSpec>> {
+    let file: SpecFile = toml::from_str(text).map_err(|e| invalid_data(e.to_string()))?;
+    checked(file.instruments)
+}
+
+pub fn specs_from_json(text: &str) -> io::Result<Vec<InstrumentSpec>> {
+    let file: SpecFile = serde_json::from_str(text).map_err(|e| invalid_data(e.to_string()))?;
+    checked(file.instruments)
+}
+
+/// Validate every spec, at most one per symbol
+fn checked(specs: Vec<InstrumentSpec>) -> io::Result<Vec<InstrumentSpec>> {
+    for (i, spec) in specs.iter().enumerate() {
+        spec.validate()?;
+        if specs[..i].iter().any(|other| other.symbol == spec.symbol) {
+            return Err(invalid_data(format!(
+                "Duplicate instrument symbol: {}",
+                spec.symbol
+            )));
+        }
+    }
+    Ok(specs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::order_types::{IncomingSide, TimeInForce};
+    use crate::data::orders::inbound_orders::{IncomingIcebergOrder, IncomingLimitOrder};
+
+    fn limit(price: u64, qty: u32) -> IncomingOrder {
+        IncomingOrder::InboundLimit(IncomingLimitOrder {
+            order_id: 1,
+            price,
+            qty,
+            side: IncomingSide::Buy,
+            account_id: 0,
+            post_only: None,
+            time_in_force: TimeInForce::Gtc,
+            symbol: 0,
+        })
+    }
+
+    #[test]
+    fn test_check_reasons() {
+        let spec = InstrumentSpec {
+            tick_size: 5,
+            lot_size: 10,
+            min_qty: 20,
+            max_qty: 1_000,
+            min_price: 50,
+            max_price: 500,
+            ..InstrumentSpec::default()
+        };
+
+        assert_eq!(spec.check(&limit(100, 30)), None);
+        assert_eq!(
+            spec.check(&limit(101, 30)),
+            Some(RejectReason::PriceNotOnTick)
+        );
+        assert_eq!(
+            spec.check(&limit(45, 30)),
+            Some(RejectReason::PriceBelowMin)
+        );
+        assert_eq!(
+            spec.check(&limit(505, 30)),
+            Some(RejectReason::PriceAboveMax)
+        );
+        assert_eq!(spec.check(&limit(100, 35)), Some(RejectReason::QtyNotOnLot));
+        assert_eq!(spec.check(&limit(100, 10)), Some(RejectReason::QtyBelowMin));
+        assert_eq!(
+            spec.check(&limit(100, 1_010)),
+            Some(RejectReason::QtyAboveMax)
+        );
+
+        let iceberg = IncomingOrder::InboundIceberg(IncomingIcebergOrder {
+            order_id: 2,
+            price: 100,
+            display_qty: 5,
+            total_qty: 100,
+            side: IncomingSide::Sell,
+            account_id: 0,
+            symbol: 0,
+        });
+        assert_eq!(spec.check(&iceberg), Some(RejectReason::QtyNotOnLot));
+        assert_eq!(InstrumentSpec::default().check(&limit(101, 35)), None);
+    }
+
+    #[test]
+    fn test_load_toml_and_json() {
+        let toml = specs_from_toml("[[instruments]]\nsymbol = 2\ntick_size = 5\n").unwrap();
+        let json = specs_from_json(r#"{"instruments":[{"symbol":2,"tick_size":5}]}"#).unwrap();
+        assert_eq!(toml, json);
+        assert_eq!(toml[0].tick_size, 5);
+        assert_eq!(toml[0].lot_size, 1);
+
+        for text in [
+            "[[instruments]]\ntick_size = 0\n",
+            "[[instruments]]\nmin_qty = 5\nmax_qty = 4\n",
+            "[[instruments]]\nsymbol = 1\n[[instruments]]\nsymbol = 1\n",
+            "[[instruments]]\ntick = 5\n",
+        ] {
+            assert!(specs_from_toml(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,482 @@
+use crate::data::book_event::{
+    BandCancelEvent, BookEvent, CancelEvent, KillEvent, RejectReason, StopInsertEvent, TriggerEvent,
+};
+use crate::data::order_types::{IncomingSide, PostOnlyMode, StpMode, SymbolId, TimeInForce};
+use crate::data::orders::inbound_orders::{
+    IncomingCancelOrder, IncomingIcebergOrder, IncomingLimitOrder, IncomingMarketOrder,
+    IncomingModifyOrder, IncomingStopOrder,
+};
+use crate::data::orders::resting_orders::{OrderId, RestingOrder};
+use crate::engine::instrument::InstrumentSpec;
+use crate::engine::price_band::{BandReference, PriceBand};
+use crate::orderbook::order_book::OrderBook;
+use crate::orderbook::stop_book::StopBook;
+use std::collections::{BTreeSet, VecDeque};
+
+pub const MICROS_PER_DAY: i64 = 86_400_000_000;
+
+/// Saved state of one market, see `Engine::restore`
+pub struct MarketState {
+    pub symbol: SymbolId,
+    pub last_trade_price: Option<u64>,
+    pub orders: Vec<RestingOrder>, // In `OrderBook::fifo_orders` order
+    pub stops: Vec<IncomingStopOrder>, // In `StopBook::iter` order
+}
+
+/// Book, pending stops and expiries of a single symbol
+/// The engine routes each input to its market and owns time and sequencing for all of them
+pub struct Market {
+    book: OrderBook,
+    stops: StopBook,
+    spec: InstrumentSpec,
+    price_band: Option<PriceBand>,
+
+    now: i64,                           // Engine time, set by the engine before each input
+    expiries: BTreeSet<(i64, OrderId)>, // Resting DAY/GTD orders by expiry
+    last_trade_price: Option<u64>,
+}
+
+impl Market {
+    pub fn new(symbol: SymbolId, capacity: usize) -> Self {
+        Self {
+            book: OrderBook::new(capacity),
+            stops: StopBook::default(),
+            spec: InstrumentSpec {
+                symbol,
+                ..InstrumentSpec::default()
+            },
+            price_band: None,
+            now: 0,
+            expiries: BTreeSet::new(),
+            last_trade_price: None,
+        }
+    }
+
+    /// Rebuild a market from saved state, expiries are rebuilt from the orders
+    pub fn restore(capacity: usize, now: i64, state: MarketState) -> Self {
+        let mut market = Self::new(state.symbol, capacity);
+        market.set_time(now);
+        market.last_trade_price = state.last_trade_price;
+
+        for order in state.orders {
+            if let Some(expires_at) = order.expires_at {
+                market.expiries.insert((expires_at, order.order_id));
+            }
+            market.book.restore_order(order);
+        }
+        for stop in state.stops {
+            market.stops.insert(stop);
+        }
+
+        market
+    }
+
+    #[inline]
+    pub fn set_time(&mut self, now: i64) {
+        self.now = now;
+        self.book.set_time(now);
+    }
+
+    /// Expire every DAY/GTD order that is due at the current time
+    pub fn expire_due(&mut self) -> Vec<BookEvent> {
+        let mut events = vec![];
+        while let Some(&(expires_at, order_id)) = self.expiries.first() {
+            if expires_at > self.now {
+                break;
+            }
+            self.expiries.pop_first();
+
+            // Orders filled or cancelled in the meantime are skipped
+            if let Some(event) = self.book.expire_order(order_id, expires_at) {
+                events.push(event);
+            }
+        }
+
+        events
+    }
+
+    /// Whether a new order may not use `order_id` under `DuplicateIdPolicy::Live`
+    #[inline]
+    pub fn is_live(&self, order_id: OrderId) -> bool {
+        self.book.get_index(order_id).is_some() || self.stops.contains(order_id)
+    }
+
+    /// Release stops triggered by trades in `events`, appending what they produce
+    ///
+    /// Triggered stops are queued in StopBook order and run one at a time.
+    /// Trades from a released stop can trigger more stops, which join the back of the queue.
+    pub fn release_stops(&mut self, events: &mut Vec<BookEvent>) {
+        let mut queue = VecDeque::new();
+        let mut scanned = 0;
+
+        loop {
+            for event in &events[scanned..] {
+                if let BookEvent::Match(fill) = event {
+                    self.last_trade_price = Some(fill.price);
+                }
+            }
+            scanned = events.len();
+
+            if let Some(last_price) = self.last_trade_price {
+                queue.extend(self.stops.take_triggered(last_price));
+            }
+
+            let Some(stop) = queue.pop_front() else {
+                return;
+            };
+
+            events.push(BookEvent::Trigger(TriggerEvent {
+                order_id: stop.order_id,
+                trigger_price: stop.trigger_price,
+                last_price: self.last_trade_price.unwrap_or_default(),
+                ts: self.now,
+            }));
+            scanned += 1;
+
+            events.extend(match stop.limit_price {
+                Some(price) => self.match_limit(IncomingLimitOrder {
+                    order_id: stop.order_id,
+                    price,
+                    qty: stop.qty,
+                    side: stop.side,
+                    account_id: stop.account_id,
+                    post_only: None,
+                    time_in_force: TimeInForce::Gtc,
+                    symbol: stop.symbol,
+                }),
+                None => self.match_market(IncomingMarketOrder {
+                    order_id: stop.order_id,
+                    qty: stop.qty,
+                    side: stop.side,
+                    account_id: stop.account_id,
+                    symbol: stop.symbol,
+                }),
+            });
+        }
+    }
+
+    pub fn match_limit(&mut self, mut order: IncomingLimitOrder) -> Vec<BookEvent> {
+        // DAY is pinned to the end of the current engine day on arrival
+        if order.time_in_force == TimeInForce::Day {
+            let end_of_day = (self.now.div_euclid(MICROS_PER_DAY) + 1) * MICROS_PER_DAY;
+            order.time_in_force = TimeInForce::Gtd(end_of_day);
+        }
+
+        if let TimeInForce::Gtd(expires_at) = order.time_in_force
+            && expires_at <= self.now
+        {
+            return vec![BookEvent::reject(
+                Some(order.order_id),
+                RejectReason::ExpiredOnArrival,
+                self.now,
+            )];
+        }
+
+        if self.outside_band(order.price) {
+            return vec![BookEvent::reject(
+                Some(order.order_id),
+                RejectReason::PriceOutsideBand,
+                self.now,
+            )];
+        }
+
+        if order.post_only.is_some() {
+            return self.post_limit(order);
+        }
+
+        // FOK checks liquidity up front so nothing trades unless all of it can
+        if order.time_in_force == TimeInForce::Fok
+            && !self
+                .book
+                .quote_for(&order.side, order.qty, Some(order.price), order.account_id)
+                .is_complete()
+        {
+            return vec![kill(&order, order.qty, self.now)];
+        }
+
+        let (mut fill, remaining) = match order.side {
+            IncomingSide::Buy => {
+                let mut iter = self.book.match_limit_buy(&order);
+                let fill: Vec<BookEvent> = iter.by_ref().collect();
+                (fill, iter.remaining())
+            }
+
+            IncomingSide::Sell => {
+                let mut iter = self.book.match_limit_sell(&order);
+                let fill: Vec<BookEvent> = iter.by_ref().collect();
+                (fill, iter.remaining())
+            }
+        };
+
+        if remaining > 0 {
+            match order.time_in_force {
+                TimeInForce::Ioc | TimeInForce::Fok => fill.push(kill(&order, remaining, self.now)),
+                _ => fill.push(self.rest_limit(order, remaining)),
+            }
+        }
+
+        fill
+    }
+
+    /// Rest a limit order and track its expiry if it has one
+    fn rest_limit(&mut self, order: IncomingLimitOrder, remaining: u32) -> BookEvent {
+        if let Some(expires_at) = order.time_in_force.expires_at() {
+            self.expiries.insert((expires_at, order.order_id));
+        }
+
+        match order.side {
+            IncomingSide::Buy => self.book.insert_bids(order, remaining),
+            IncomingSide::Sell => self.book.insert_asks(order, remaining),
+        }
+    }
+
+    pub fn match_market(&mut self, order: IncomingMarketOrder) -> Vec<BookEvent> {
+        let empty = match order.side {
+            IncomingSide::Buy => self.book.best_ask().is_none(),
+            IncomingSide::Sell => self.book.best_bid().is_none(),
+        };
+
+        if empty {
+            return vec![BookEvent::reject(
+                Some(order.order_id),
+                RejectReason::NoLiquidity,
+                self.now,
+            )];
+        }
+
+        // Buys may lift asks up to the top of the band, sells hit bids down to the bottom
+        let band_price = self.band_bounds().map(|(lower, upper)| match order.side {
+            IncomingSide::Buy => upper,
+            IncomingSide::Sell => lower,
+        });
+
+        let (mut fill, remaining) = match order.side {
+            IncomingSide::Buy => {
+                let mut iter = self.book.match_market_buy(&order, band_price);
+                let fill: Vec<BookEvent> = iter.by_ref().collect();
+                (fill, iter.remaining())
+            }
+
+            IncomingSide::Sell => {
+                let mut iter = self.book.match_market_sell(&order, band_price);
+                let fill: Vec<BookEvent> = iter.by_ref().collect();
+                (fill, iter.remaining())
+            }
+        };
+
+        // Liquidity left on the other side means the band stopped the sweep
+        let blocked = match order.side {
+            IncomingSide::Buy => self.book.best_ask().is_some(),
+            IncomingSide::Sell => self.book.best_bid().is_some(),
+        };
+
+        if let Some(band_price) = band_price
+            && remaining > 0
+            && blocked
+        {
+            fill.push(BookEvent::BandCancel(BandCancelEvent {
+                order_id: order.order_id,
+                qty: remaining,
+                band_price,
+                ts: self.now,
+            }));
+        }
+
+        fill
+    }
+
+    /// Post-only orders never run through matching, they either rest or get rejected
+    pub fn post_limit(&mut self, mut order: IncomingLimitOrder) -> Vec<BookEvent> {
+        let crossing = match order.side {
+            IncomingSide::Buy => self.book.best_ask().filter(|ask| ask.0 <= order.price),
+            IncomingSide::Sell => self
+                .book
+                .best_bid()
+                .map(|bid| &bid.0)
+                .filter(|bid| bid.0 >= order.price),
+        };
+
+        if let Some(opposite) = crossing {
+            // Slide to the nearest tick behind the opposite best
+            let tick = self.spec.tick_size;
+            let slid = match order.side {
+                IncomingSide::Buy => opposite
+                    .0
+                    .checked_sub(1)
+                    .map(|price| price - price % tick)
+                    .filter(|price| *price > 0),
+                IncomingSide::Sell => (opposite.0 / tick)
+                    .checked_add(1)
+                    .and_then(|ticks| ticks.checked_mul(tick)),
+            };
+
+            match (order.post_only, slid) {
+                (Some(PostOnlyMode::Slide), Some(price)) => {
+                    // The new price has to fit the instrument like the original one did
+                    if let Some(reason) = self.spec.check_price(price) {
+                        return vec![BookEvent::reject(Some(order.order_id), reason, self.now)];
+                    }
+                    order.price = price;
+                }
+                _ => {
+                    return vec![BookEvent::reject(
+                        Some(order.order_id),
+                        RejectReason::PostOnlyWouldCross,
+                        self.now,
+                    )];
+                }
+            }
+        }
+
+        let qty = order.qty;
+        vec![self.rest_limit(order, qty)]
+    }
+
+    /// Icebergs take liquidity with their full size, only the resting part is sliced
+    pub fn match_iceberg(&mut self, order: IncomingIcebergOrder) -> Vec<BookEvent> {
+        if self.outside_band(order.price) {
+            return vec![BookEvent::reject(
+                Some(order.order_id),
+                RejectReason::PriceOutsideBand,
+                self.now,
+            )];
+        }
+
+        match order.side {
+            IncomingSide::Buy => {
+                let mut iter = self.book.match_iceberg_buy(&order);
+                let mut fill: Vec<BookEvent> = iter.by_ref().collect();
+                let remaining = iter.remaining();
+
+                if remaining > 0 {
+                    fill.push(self.book.insert_bids(order, remaining));
+                }
+
+                fill
+            }
+
+            IncomingSide::Sell => {
+                let mut iter = self.book.match_iceberg_sell(&order);
+                let mut fill: Vec<BookEvent> = iter.by_ref().collect();
+                let remaining = iter.remaining();
+
+                if remaining > 0 {
+                    fill.push(self.book.insert_asks(order, remaining));
+                }
+
+                fill
+            }
+        }
+    }
+
+    /// Park a stop order, it is released once the last trade price reaches its trigger
+    pub fn match_stop(&mut self, order: IncomingStopOrder) -> Vec<BookEvent> {
+        let event = BookEvent::StopInsert(StopInsertEvent {
+            order_id: order.order_id,
+            trigger_price: order.trigger_price,
+            limit_price: order.limit_price,
+            side: order.side.clone(),
+            qty: order.qty,
+            ts: self.now,
+        });
+
+        self.stops.insert(order);
+        vec![event]
+    }
+
+    /// Amends that move a live order to a new price must land inside the band
+    pub fn match_modify(&mut self, order: IncomingModifyOrder) -> Vec<BookEvent> {
+        let reprice = order.qty > 0
+            && self
+                .book
+                .get_order(order.order_id)
+                .is_some_and(|resting| resting.price != order.price);
+
+        if reprice && self.outside_band(order.price) {
+            return vec![BookEvent::reject(
+                Some(order.order_id),
+                RejectReason::PriceOutsideBand,
+                self.now,
+            )];
+        }
+
+        self.book.amend_order(&order)
+    }
+
+    pub fn match_cancel(&mut self, order: IncomingCancelOrder) -> Vec<BookEvent> {
+        if let Some(stop) = self.stops.cancel(order.order_id) {
+            return vec![BookEvent::Cancel(CancelEvent {
+                order_id: stop.order_id,
+                qty: stop.qty,
+                ts: self.now,
+            })];
+        }
+
+        self.book.cancel_order(order.order_id)
+    }
+
+    /// Prices and sizes new orders must respect, permissive by default
+    #[inline]
+    pub fn set_spec(&mut self, spec: InstrumentSpec) {
+        self.spec = spec;
+    }
+
+    #[inline]
+    pub fn spec(&self) -> &InstrumentSpec {
+        &self.spec
+    }
+
+    #[inline]
+    pub fn set_price_band(&mut self, price_band: Option<PriceBand>) {
+        self.price_band = price_band;
+    }
+
+    /// Lowest and highest price orders may trade at right now, None if nothing is banded
+    pub fn band_bounds(&self) -> Option<(u64, u64)> {
+        let band = self.price_band?;
+        let reference = match band.reference {
+            BandReference::LastTrade => self.last_trade_price?,
+            BandReference::Mid => {
+                let bid = self.book.best_bid()?.0.0;
+                let ask = self.book.best_ask()?.0;
+                bid.midpoint(ask)
+            }
+        };
+
+        Some(band.bounds(reference))
+    }
+
+    fn outside_band(&self, price: u64) -> bool {
+        self.band_bounds()
+            .is_some_and(|(lower, upper)| price < lower || price > upper)
+    }
+
+    #[inline]
+    pub fn set_stp_mode(&mut self, stp_mode: Option<StpMode>) {
+        self.book.set_stp_mode(stp_mode);
+    }
+
+    #[inline]
+    pub fn last_trade_price(&self) -> Option<u64> {
+        self.last_trade_price
+    }
+
+    #[inline]
+    pub fn get_stops(&self) -> &StopBook {
+        &self.stops
+    }
+
+    #[inline]
+    pub fn get_book(&self) -> &OrderBook {
+        &self.book
+    }
+}
+
+fn kill(order: &IncomingLimitOrder, qty: u32, ts: i64) -> BookEvent {
+    BookEvent::Kill(KillEvent {
+        order_id: order.order_id,
+        qty,
+        time_in_force: order.time_in_force,
+        ts,
+    })
+}
@@ -1,77 +1,1247 @@
-use crate::data::book_event::BookEvent;
-use crate::data::order_types::{IncomingOrder, IncomingSide};
-use crate::data::orders::inbound_orders::{
-    IncomingCancelOrder, IncomingLimitOrder, IncomingMarketOrder,
-};
+use crate::data::book_event::{BookEvent, EventEnvelope, RejectReason};
+use crate::data::book_snapshot::BookSnapshot;
+use crate::data::order_types::{DEFAULT_SYMBOL, IncomingOrder, StpMode, SymbolId, TimeInForce};
+use crate::data::orders::resting_orders::OrderId;
+use crate::engine::clock::{Clock, LogicalClock};
+use crate::engine::instrument::InstrumentSpec;
+use crate::engine::market::{Market, MarketState};
+use crate::engine::price_band::PriceBand;
 use crate::orderbook::order_book::OrderBook;
+use crate::orderbook::stop_book::StopBook;
+use rustc_hash::FxHashSet;
+use std::collections::BTreeMap;
 
-#[derive(Default)]
+/// Which order ids a new order may not reuse
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateIdPolicy {
+    #[default]
+    Live, // Ids of resting or pending stop orders in the same market
+    Session, // Any id seen since the engine started, in any market
+}
+
+/// Engine counters that have to survive a snapshot and restore
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EngineCounters {
+    pub seq: u64,
+    pub input_seq: u64,
+    pub now: i64,
+}
+
+/// Routes every input to the market of its symbol
+///
+/// Time, sequencing and the duplicate id policy are shared by all markets, so the event
+/// stream stays totally ordered however many books there are.
 pub struct Engine {
-    book: OrderBook,
+    markets: BTreeMap<SymbolId, Market>,
+
+    clock: Box<dyn Clock>,
+    now: i64, // Engine time of the current input, never moves backwards
+
+    id_policy: DuplicateIdPolicy,
+    seen_ids: FxHashSet<OrderId>, // Only filled under DuplicateIdPolicy::Session
+
+    seq: u64,       // Last engine sequence number handed out
+    input_seq: u64, // Last input sequence number handed out
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new(0)
+    }
 }
 
 impl Engine {
+    /// Engine with a single market, `DEFAULT_SYMBOL`
     pub fn new(capacity: usize) -> Self {
+        Self::with_symbols(capacity, [DEFAULT_SYMBOL])
+    }
+
+    /// Engine with one market per symbol, each book gets `capacity`
+    pub fn with_symbols(capacity: usize, symbols: impl IntoIterator<Item = SymbolId>) -> Self {
+        Self::from_markets(
+            symbols
+                .into_iter()
+                .map(|symbol| (symbol, Market::new(symbol, capacity)))
+                .collect(),
+        )
+    }
+
+    fn from_markets(markets: BTreeMap<SymbolId, Market>) -> Self {
+        assert!(!markets.is_empty(), "Engine needs at least one market");
+
         Self {
-            book: OrderBook::new(capacity),
+            markets,
+            clock: Box::new(LogicalClock::default()),
+            now: 0,
+            id_policy: DuplicateIdPolicy::default(),
+            seen_ids: FxHashSet::default(),
+            seq: 0,
+            input_seq: 0,
         }
     }
-    pub fn match_order(&mut self, order: IncomingOrder) -> Vec<BookEvent> {
-        match order {
-            IncomingOrder::InboundLimit(limit) => self.match_limit(limit),
-            IncomingOrder::InboundMarket(market) => self.match_market(market),
-            IncomingOrder::InboundCancel(cancel) => self.match_cancel(cancel),
+
+    /// Rebuild an engine from saved state, one market per `MarketState`
+    ///
+    /// Clock, self-trade prevention, duplicate id policy and instrument specs are configuration
+    /// and start at their defaults.
+    pub fn restore(
+        capacity: usize,
+        counters: EngineCounters,
+        markets: impl IntoIterator<Item = MarketState>,
+        seen_ids: impl IntoIterator<Item = OrderId>,
+    ) -> Self {
+        let mut engine = Self::from_markets(
+            markets
+                .into_iter()
+                .map(|state| (state.symbol, Market::restore(capacity, counters.now, state)))
+                .collect(),
+        );
+        engine.seq = counters.seq;
+        engine.input_seq = counters.input_seq;
+        engine.now = counters.now;
+        engine.seen_ids.extend(seen_ids);
+
+        engine
+    }
+
+    #[inline]
+    pub fn counters(&self) -> EngineCounters {
+        EngineCounters {
+            seq: self.seq,
+            input_seq: self.input_seq,
+            now: self.now,
         }
     }
 
-    pub fn match_limit(&mut self, order: IncomingLimitOrder) -> Vec<BookEvent> {
-        match order.side {
-            IncomingSide::Buy => {
-                let mut iter = self.book.match_limit_buy(&order);
-                let mut fill: Vec<BookEvent> = iter.by_ref().collect();
-                let remaining = iter.remaining();
+    /// Ids seen this session, sorted (only tracked under DuplicateIdPolicy::Session)
+    pub fn seen_ids(&self) -> Vec<OrderId> {
+        let mut ids: Vec<OrderId> = self.seen_ids.iter().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
 
-                if remaining > 0 {
-                    fill.push(self.book.insert_bids(order, remaining));
-                }
+    /// Source of engine time, the logical clock (input sequence number) by default
+    /// Swap in a replay clock for timestamps from the input or the wall clock for system time
+    #[inline]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
 
-                fill
-            }
+    /// Process one input and wrap its events for the journal
+    pub fn submit(&mut self, order: IncomingOrder) -> Vec<EventEnvelope> {
+        let events = self.process(order);
+        self.seal(self.input_seq, events)
+    }
 
-            IncomingSide::Sell => {
-                let mut iter = self.book.match_limit_sell(&order);
-                let mut fill: Vec<BookEvent> = iter.by_ref().collect();
-                let remaining = iter.remaining();
+    /// Final state of every book in symbol order, journaled outside of any input (input_seq 0)
+    pub fn snapshot(&mut self, include_orders: bool) -> Vec<EventEnvelope> {
+        let events = self
+            .markets
+            .iter()
+            .map(|(&symbol, market)| {
+                let snapshot = BookSnapshot {
+                    symbol,
+                    ..market.get_book().snapshot(include_orders)
+                };
+                (symbol, BookEvent::BookSnapshot(snapshot))
+            })
+            .collect();
+        self.seal(0, events)
+    }
 
-                if remaining > 0 {
-                    fill.push(self.book.insert_asks(order, remaining));
+    fn seal(&mut self, input_seq: u64, events: Vec<(SymbolId, BookEvent)>) -> Vec<EventEnvelope> {
+        events
+            .into_iter()
+            .enumerate()
+            .map(|(batch_index, (symbol, event))| {
+                self.seq += 1;
+                EventEnvelope {
+                    seq: self.seq,
+                    input_seq,
+                    batch_index: batch_index as u32,
+                    symbol,
+                    event,
                 }
+            })
+            .collect()
+    }
+
+    /// Process one input, dropping the symbol each event belongs to
+    pub fn match_order(&mut self, order: IncomingOrder) -> Vec<BookEvent> {
+        self.process(order)
+            .into_iter()
+            .map(|(_, event)| event)
+            .collect()
+    }
+
+    fn process(&mut self, order: IncomingOrder) -> Vec<(SymbolId, BookEvent)> {
+        self.input_seq += 1;
+        self.clock.tick(self.input_seq, order.supplied_ts());
+        let mut events = self.advance_clock(self.clock.now());
+
+        // TIME only moves the clock
+        let Some(symbol) = order.symbol() else {
+            return events;
+        };
+
+        let reason = validate(&order)
+            .or_else(|| match self.markets.get(&symbol) {
+                Some(market) => market.spec().check(&order),
+                None => Some(RejectReason::UnknownSymbol),
+            })
+            .or_else(|| self.check_duplicate(symbol, &order));
+        if let Some(reason) = reason {
+            events.push((
+                symbol,
+                BookEvent::reject(order.order_id(), reason, self.now),
+            ));
+            return events;
+        }
 
-                fill
+        let market = self.markets.get_mut(&symbol).expect("Checked above");
+        let mut produced = match order {
+            IncomingOrder::InboundLimit(limit) => market.match_limit(limit),
+            IncomingOrder::InboundMarket(order) => market.match_market(order),
+            IncomingOrder::InboundIceberg(iceberg) => market.match_iceberg(iceberg),
+            IncomingOrder::InboundStop(stop) => market.match_stop(stop),
+            IncomingOrder::InboundModify(modify) => market.match_modify(modify),
+            IncomingOrder::InboundCancel(cancel) => market.match_cancel(cancel),
+            IncomingOrder::InboundTime(_) | IncomingOrder::InboundInvalid(_) => {
+                unreachable!("Handled above")
             }
+        };
+
+        // Stops only trigger on trades in their own market
+        market.release_stops(&mut produced);
+        events.extend(produced.into_iter().map(|event| (symbol, event)));
+
+        events
+    }
+
+    /// Reject new orders that reuse an id, according to the duplicate id policy
+    /// Overwriting a live id would orphan the old order in the book
+    fn check_duplicate(&mut self, symbol: SymbolId, order: &IncomingOrder) -> Option<RejectReason> {
+        let order_id = match order {
+            IncomingOrder::InboundLimit(order) => order.order_id,
+            IncomingOrder::InboundMarket(order) => order.order_id,
+            IncomingOrder::InboundIceberg(order) => order.order_id,
+            IncomingOrder::InboundStop(order) => order.order_id,
+            _ => return None,
+        };
+
+        let duplicate = match self.id_policy {
+            DuplicateIdPolicy::Live => self.markets[&symbol].is_live(order_id),
+            DuplicateIdPolicy::Session => !self.seen_ids.insert(order_id),
+        };
+
+        duplicate.then_some(RejectReason::DuplicateOrderId)
+    }
+
+    /// Move the engine clock forward and expire every DAY/GTD order that is due, in every market
+    /// The clock never moves backwards
+    pub fn advance_clock(&mut self, now: i64) -> Vec<(SymbolId, BookEvent)> {
+        self.now = self.now.max(now);
+
+        let mut events = vec![];
+        for (&symbol, market) in &mut self.markets {
+            market.set_time(self.now);
+            events.extend(market.expire_due().into_iter().map(|event| (symbol, event)));
         }
+
+        events
     }
 
-    pub fn match_market(&mut self, order: IncomingMarketOrder) -> Vec<BookEvent> {
-        match order.side {
-            IncomingSide::Buy => {
-                let mut iter = self.book.match_market_buy(&order);
-                iter.by_ref().collect()
-            }
+    #[inline]
+    pub fn set_duplicate_id_policy(&mut self, id_policy: DuplicateIdPolicy) {
+        self.id_policy = id_policy;
+    }
 
-            IncomingSide::Sell => {
-                let mut iter = self.book.match_market_sell(&order);
-                iter.by_ref().collect()
+    /// Reference data for the market of `spec.symbol`, false if there is no such market
+    /// Configuration like the clock, it is not part of saved state
+    pub fn set_instrument_spec(&mut self, spec: InstrumentSpec) -> bool {
+        match self.markets.get_mut(&spec.symbol) {
+            Some(market) => {
+                market.set_spec(spec);
+                true
             }
+            None => false,
         }
     }
 
-    pub fn match_cancel(&mut self, order: IncomingCancelOrder) -> Vec<BookEvent> {
-        self.book.cancel_order(order.order_id)
+    /// Self-trade prevention for every order in every market, None (default) allows self-trades
+    pub fn set_stp_mode(&mut self, stp_mode: Option<StpMode>) {
+        for market in self.markets.values_mut() {
+            market.set_stp_mode(stp_mode);
+        }
+    }
+
+    /// Price band for every market, None (default) lets orders trade at any price
+    /// Configuration like the clock, it is not part of saved state
+    pub fn set_price_band(&mut self, band: Option<PriceBand>) {
+        for market in self.markets.values_mut() {
+            market.set_price_band(band);
+        }
     }
 
+    #[inline]
+    pub fn market(&self, symbol: SymbolId) -> Option<&Market> {
+        self.markets.get(&symbol)
+    }
+
+    /// Every market in symbol order
+    pub fn markets(&self) -> impl Iterator<Item = (SymbolId, &Market)> {
+        self.markets
+            .iter()
+            .map(|(&symbol, market)| (symbol, market))
+    }
+
+    /// Pending stops of the first market, the only one unless several symbols are configured
+    #[inline]
+    pub fn get_stops(&self) -> &StopBook {
+        self.first_market().get_stops()
+    }
+
+    /// Book of the first market, the only one unless several symbols are configured
     #[inline]
     pub fn get_book(&self) -> &OrderBook {
-        &self.book
+        self.first_market().get_book()
+    }
+
+    #[inline]
+    fn first_market(&self) -> &Market {
+        self.markets
+            .values()
+            .next()
+            .expect("Engine has at least one market")
+    }
+}
+
+/// Static checks on an input before it reaches the book
+fn validate(order: &IncomingOrder) -> Option<RejectReason> {
+    match order {
+        IncomingOrder::InboundLimit(order) => {
+            if order.qty == 0 {
+                Some(RejectReason::ZeroQuantity)
+            } else if order.price == 0 {
+                Some(RejectReason::ZeroPrice)
+            } else if order.post_only.is_some()
+                && matches!(order.time_in_force, TimeInForce::Ioc | TimeInForce::Fok)
+            {
+                // Post-only only ever rests, IOC/FOK never do
+                Some(RejectReason::PostOnlyImmediateTif)
+            } else {
+                None
+            }
+        }
+        IncomingOrder::InboundMarket(order) => {
+            (order.qty == 0).then_some(RejectReason::ZeroQuantity)
+        }
+        IncomingOrder::InboundIceberg(order) => {
+            if order.total_qty == 0 || order.display_qty == 0 {
+                Some(RejectReason::ZeroQuantity)
+            } else if order.price == 0 {
+                Some(RejectReason::ZeroPrice)
+            } else {
+                None
+            }
+        }
+        IncomingOrder::InboundStop(order) => {
+            if order.qty == 0 {
+                Some(RejectReason::ZeroQuantity)
+            } else if order.trigger_price == 0 || order.limit_price == Some(0) {
+                Some(RejectReason::ZeroPrice)
+            } else {
+                None
+            }
+        }
+        // Amending to zero quantity is a cancel
+        IncomingOrder::InboundModify(order) => {
+            (order.price == 0).then_some(RejectReason::ZeroPrice)
+        }
+        IncomingOrder::InboundCancel(_) | IncomingOrder::InboundTime(_) => None,
+        IncomingOrder::InboundInvalid(order) => Some(order.reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::book_event::SelfTradeEvent;
+    use crate::data::order_types::{IncomingSide, PostOnlyMode};
+    use crate::data::orders::inbound_orders::{
+        IncomingCancelOrder, IncomingInvalidOrder, IncomingLimitOrder, IncomingMarketOrder,
+        IncomingModifyOrder, IncomingStopOrder, IncomingTimeUpdate,
+    };
+    use crate::engine::clock::ReplayClock;
+    use crate::engine::market::MICROS_PER_DAY;
+    use crate::engine::price_band::BandReference;
+    use crate::orderbook::util::price_key::PriceKey;
+
+    fn limit_order(id: u64, price: u64, qty: u32, side: IncomingSide) -> IncomingLimitOrder {
+        IncomingLimitOrder {
+            order_id: id,
+            price,
+            qty,
+            side,
+            account_id: 0,
+            post_only: None,
+            time_in_force: TimeInForce::Gtc,
+            symbol: 0,
+        }
+    }
+
+    fn limit(id: u64, price: u64, qty: u32, side: IncomingSide) -> IncomingOrder {
+        IncomingOrder::InboundLimit(limit_order(id, price, qty, side))
+    }
+
+    fn post_only(
+        id: u64,
+        price: u64,
+        qty: u32,
+        side: IncomingSide,
+        mode: PostOnlyMode,
+    ) -> IncomingOrder {
+        IncomingOrder::InboundLimit(IncomingLimitOrder {
+            post_only: Some(mode),
+            ..limit_order(id, price, qty, side)
+        })
+    }
+
+    fn with_tif(
+        id: u64,
+        price: u64,
+        qty: u32,
+        side: IncomingSide,
+        tif: TimeInForce,
+    ) -> IncomingOrder {
+        IncomingOrder::InboundLimit(IncomingLimitOrder {
+            time_in_force: tif,
+            ..limit_order(id, price, qty, side)
+        })
+    }
+
+    fn market(id: u64, qty: u32, side: IncomingSide) -> IncomingOrder {
+        IncomingOrder::InboundMarket(IncomingMarketOrder {
+            order_id: id,
+            qty,
+            side,
+            account_id: 0,
+            symbol: 0,
+        })
+    }
+
+    fn time(ts: i64) -> IncomingOrder {
+        IncomingOrder::InboundTime(IncomingTimeUpdate { ts })
+    }
+
+    fn stop(
+        id: u64,
+        trigger: u64,
+        limit: Option<u64>,
+        qty: u32,
+        side: IncomingSide,
+    ) -> IncomingOrder {
+        IncomingOrder::InboundStop(IncomingStopOrder {
+            order_id: id,
+            trigger_price: trigger,
+            limit_price: limit,
+            qty,
+            side,
+            account_id: 0,
+            symbol: 0,
+        })
+    }
+
+    fn owned(id: u64, price: u64, qty: u32, side: IncomingSide, account_id: u64) -> IncomingOrder {
+        IncomingOrder::InboundLimit(IncomingLimitOrder {
+            account_id,
+            ..limit_order(id, price, qty, side)
+        })
+    }
+
+    fn self_trade(event: &BookEvent) -> &SelfTradeEvent {
+        match event {
+            BookEvent::SelfTrade(stp) => stp,
+            _ => panic!("Expected SelfTradeEvent"),
+        }
+    }
+
+    #[test]
+    fn test_post_only_reject_when_crossing() {
+        let mut engine = Engine::default();
+        engine.match_order(limit(1, 100, 5, IncomingSide::Sell));
+
+        let events = engine.match_order(post_only(
+            2,
+            101,
+            5,
+            IncomingSide::Buy,
+            PostOnlyMode::Reject,
+        ));
+
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            BookEvent::Reject(reject) => {
+                assert_eq!(reject.order_id, Some(2));
+                assert_eq!(reject.reason, RejectReason::PostOnlyWouldCross);
+            }
+            _ => panic!("Expected RejectEvent"),
+        }
+
+        // Resting ask is untouched
+        assert_eq!(engine.get_book().get_order(1).unwrap().qty, 5);
+        assert!(engine.get_book().get_order(2).is_none());
+    }
+
+    #[test]
+    fn test_post_only_slide_rests_behind_opposite_best() {
+        let mut engine = Engine::default();
+        engine.match_order(limit(1, 100, 5, IncomingSide::Buy));
+
+        let events =
+            engine.match_order(post_only(2, 95, 5, IncomingSide::Sell, PostOnlyMode::Slide));
+
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            BookEvent::Insert(insert) => {
+                assert_eq!(insert.order_id, 2);
+                assert_eq!(insert.price, 101);
+            }
+            _ => panic!("Expected InsertEvent"),
+        }
+
+        assert_eq!(engine.get_book().best_ask().unwrap().0, 101);
+        assert_eq!(engine.get_book().get_order(1).unwrap().qty, 5);
+    }
+
+    #[test]
+    fn test_post_only_slide_stays_on_tick() {
+        let mut engine = Engine::default();
+        engine.set_instrument_spec(InstrumentSpec {
+            tick_size: 5,
+            min_price: 100,
+            ..InstrumentSpec::default()
+        });
+        engine.match_order(limit(1, 105, 5, IncomingSide::Sell));
+
+        let events =
+            engine.match_order(post_only(2, 110, 5, IncomingSide::Buy, PostOnlyMode::Slide));
+        assert!(matches!(&events[0], BookEvent::Insert(insert) if insert.price == 100));
+
+        let events = engine.match_order(post_only(
+            3,
+            100,
+            5,
+            IncomingSide::Sell,
+            PostOnlyMode::Slide,
+        ));
+        assert!(matches!(&events[0], BookEvent::Insert(insert) if insert.price == 105));
+
+        // One tick below the ask at 100 is under the minimum price
+        engine.match_order(IncomingOrder::InboundCancel(IncomingCancelOrder {
+            order_id: 2,
+            symbol: DEFAULT_SYMBOL,
+        }));
+        engine.match_order(limit(4, 100, 5, IncomingSide::Sell));
+        let events =
+            engine.match_order(post_only(5, 100, 5, IncomingSide::Buy, PostOnlyMode::Slide));
+        assert_eq!(reject_reason(&events[0]), RejectReason::PriceBelowMin);
+    }
+
+    #[test]
+    fn test_post_only_with_ioc_or_fok_rejected() {
+        let mut engine = Engine::default();
+
+        for (id, tif) in [(1, TimeInForce::Ioc), (2, TimeInForce::Fok)] {
+            let order = IncomingOrder::InboundLimit(IncomingLimitOrder {
+                post_only: Some(PostOnlyMode::Reject),
+                time_in_force: tif,
+                ..limit_order(id, 100, 5, IncomingSide::Buy)
+            });
+            let events = engine.match_order(order);
+            assert_eq!(events.len(), 1);
+            assert_eq!(
+                reject_reason(&events[0]),
+                RejectReason::PostOnlyImmediateTif
+            );
+        }
+        assert!(engine.get_book().best_bid().is_none());
+    }
+
+    #[test]
+    fn test_post_only_rests_when_not_crossing() {
+        let mut engine = Engine::default();
+        engine.match_order(limit(1, 100, 5, IncomingSide::Sell));
+
+        let events =
+            engine.match_order(post_only(2, 99, 5, IncomingSide::Buy, PostOnlyMode::Reject));
+
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], BookEvent::Insert(_)));
+        assert_eq!(engine.get_book().best_bid().unwrap().0.0, 99);
+    }
+
+    #[test]
+    fn test_ioc_kills_remainder() {
+        let mut engine = Engine::default();
+        engine.match_order(limit(1, 100, 5, IncomingSide::Sell));
+
+        let events = engine.match_order(with_tif(2, 100, 8, IncomingSide::Buy, TimeInForce::Ioc));
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], BookEvent::Match(_)));
+        match &events[1] {
+            BookEvent::Kill(kill) => {
+                assert_eq!(kill.order_id, 2);
+                assert_eq!(kill.qty, 3);
+                assert_eq!(kill.time_in_force, TimeInForce::Ioc);
+            }
+            _ => panic!("Expected KillEvent"),
+        }
+
+        // Nothing rests
+        assert!(engine.get_book().best_bid().is_none());
+        assert!(engine.get_book().best_ask().is_none());
+    }
+
+    #[test]
+    fn test_fok_all_or_nothing() {
+        let mut engine = Engine::default();
+        engine.match_order(limit(1, 100, 5, IncomingSide::Sell));
+        engine.match_order(limit(2, 102, 5, IncomingSide::Sell));
+
+        // Only 5 available up to 101
+        let events = engine.match_order(with_tif(3, 101, 8, IncomingSide::Buy, TimeInForce::Fok));
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            BookEvent::Kill(kill) => assert_eq!(kill.qty, 8),
+            _ => panic!("Expected KillEvent"),
+        }
+        assert_eq!(engine.get_book().get_order(1).unwrap().qty, 5);
+
+        // 10 available up to 102
+        let events = engine.match_order(with_tif(4, 102, 8, IncomingSide::Buy, TimeInForce::Fok));
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| matches!(e, BookEvent::Match(_))));
+        assert_eq!(engine.get_book().get_order(2).unwrap().qty, 2);
+    }
+
+    #[test]
+    fn test_gtd_expires_on_clock() {
+        let mut engine = Engine::default();
+        engine.set_clock(Box::new(ReplayClock::default()));
+        let expires_at = MICROS_PER_DAY;
+
+        let events = engine.match_order(with_tif(
+            1,
+            100,
+            5,
+            IncomingSide::Buy,
+            TimeInForce::Gtd(expires_at),
+        ));
+        assert!(matches!(events[0], BookEvent::Insert(_)));
+
+        assert!(engine.advance_clock(expires_at - 1).is_empty());
+
+        let events = engine.advance_clock(expires_at);
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            (DEFAULT_SYMBOL, BookEvent::Expire(expire)) => {
+                assert_eq!(expire.order_id, 1);
+                assert_eq!(expire.qty, 5);
+            }
+            _ => panic!("Expected ExpireEvent"),
+        }
+        assert!(engine.get_book().get_order(1).is_none());
+        assert!(engine.get_book().best_bid().is_none());
+
+        // Already past expiry on arrival
+        let events = engine.match_order(with_tif(
+            2,
+            100,
+            5,
+            IncomingSide::Buy,
+            TimeInForce::Gtd(expires_at),
+        ));
+        match &events[0] {
+            BookEvent::Reject(reject) => assert_eq!(reject.reason, RejectReason::ExpiredOnArrival),
+            _ => panic!("Expected RejectEvent"),
+        }
+    }
+
+    #[test]
+    fn test_replay_time_line_expires_orders() {
+        let mut engine = Engine::default();
+        engine.set_clock(Box::new(ReplayClock::default()));
+        engine.match_order(time(1_000));
+        engine.match_order(with_tif(
+            1,
+            100,
+            5,
+            IncomingSide::Buy,
+            TimeInForce::Gtd(2_000),
+        ));
+
+        // Events carry the replay time, not the wall clock
+        match &engine.match_order(limit(2, 101, 5, IncomingSide::Buy))[0] {
+            BookEvent::Insert(insert) => assert_eq!(insert.ts, 1_000),
+            _ => panic!("Expected InsertEvent"),
+        }
+
+        let events = engine.match_order(time(2_000));
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            BookEvent::Expire(expire) => {
+                assert_eq!(expire.order_id, 1);
+                assert_eq!(expire.ts, 2_000);
+            }
+            _ => panic!("Expected ExpireEvent"),
+        }
+    }
+
+    #[test]
+    fn test_default_clock_stamps_input_seq() {
+        let mut engine = Engine::default();
+        engine.submit(limit(1, 100, 5, IncomingSide::Sell));
+        engine.submit(limit(2, 101, 5, IncomingSide::Sell));
+
+        let envelopes = engine.submit(market(3, 7, IncomingSide::Buy));
+        assert_eq!(envelopes.len(), 2);
+        for envelope in &envelopes {
+            match &envelope.event {
+                BookEvent::Match(fill) => assert_eq!(fill.ts, 3),
+                _ => panic!("Expected MatchEvent"),
+            }
+        }
+        assert_eq!(engine.get_book().get_order(2).unwrap().ts, 2);
+    }
+
+    #[test]
+    fn test_day_order_expires_at_end_of_day() {
+        let mut engine = Engine::default();
+        engine.match_order(with_tif(1, 100, 5, IncomingSide::Sell, TimeInForce::Day));
+        engine.match_order(limit(2, 101, 5, IncomingSide::Sell));
+
+        let expires_at = engine.get_book().get_order(1).unwrap().expires_at.unwrap();
+        assert_eq!(expires_at % MICROS_PER_DAY, 0);
+
+        let events = engine.advance_clock(expires_at);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], (_, BookEvent::Expire(_))));
+
+        // GTC order is untouched
+        assert_eq!(engine.get_book().best_ask().unwrap().0, 101);
+    }
+
+    #[test]
+    fn test_day_order_never_expires_under_logical_clock() {
+        let mut engine = Engine::default();
+        engine.submit(with_tif(1, 100, 5, IncomingSide::Sell, TimeInForce::Day));
+        assert_eq!(
+            engine.get_book().get_order(1).unwrap().expires_at,
+            Some(MICROS_PER_DAY)
+        );
+
+        // End of day is counted in inputs, far beyond any realistic stream
+        for id in 2..1_000 {
+            engine.submit(limit(id, 90, 1, IncomingSide::Buy));
+        }
+        assert_eq!(engine.get_book().best_ask().unwrap().0, 100);
+    }
+
+    #[test]
+    fn test_stop_market_triggers_on_last_trade() {
+        let mut engine = Engine::default();
+        engine.match_order(limit(1, 100, 5, IncomingSide::Sell));
+        engine.match_order(limit(2, 101, 5, IncomingSide::Sell));
+
+        let events = engine.match_order(stop(3, 100, None, 3, IncomingSide::Buy));
+        assert!(matches!(events[0], BookEvent::StopInsert(_)));
+        assert_eq!(engine.get_stops().len(), 1);
+
+        let events = engine.match_order(market(4, 2, IncomingSide::Buy));
+        assert_eq!(events.len(), 3);
+        match &events[1] {
+            BookEvent::Trigger(trigger) => {
+                assert_eq!(trigger.order_id, 3);
+                assert_eq!(trigger.last_price, 100);
+            }
+            _ => panic!("Expected TriggerEvent"),
+        }
+        match &events[2] {
+            BookEvent::Match(fill) => {
+                assert_eq!(fill.taker, 3);
+                assert_eq!(fill.price, 100);
+                assert_eq!(fill.qty, 3);
+            }
+            _ => panic!("Expected MatchEvent"),
+        }
+        assert!(engine.get_stops().is_empty());
+    }
+
+    #[test]
+    fn test_stop_cascade_is_sequential() {
+        let mut engine = Engine::default();
+        engine.match_order(limit(1, 100, 2, IncomingSide::Sell));
+        engine.match_order(limit(2, 101, 2, IncomingSide::Sell));
+        engine.match_order(limit(3, 102, 5, IncomingSide::Sell));
+
+        // The trade at 100 releases only the stop-limit (trigger 100, limit 101)
+        // Its fill at 101 then releases the stop-market triggered at 101, which lifts 102
+        engine.match_order(stop(10, 101, None, 1, IncomingSide::Buy));
+        engine.match_order(stop(11, 100, Some(101), 2, IncomingSide::Buy));
+
+        let events = engine.match_order(market(20, 2, IncomingSide::Buy));
+        let sequence: Vec<(u64, u64)> = events
+            .iter()
+            .map(|event| match event {
+                BookEvent::Match(fill) => (fill.taker, fill.price),
+                BookEvent::Trigger(trigger) => (trigger.order_id, 0),
+                _ => panic!("Unexpected event"),
+            })
+            .collect();
+
+        assert_eq!(
+            sequence,
+            vec![(20, 100), (11, 0), (11, 101), (10, 0), (10, 102)]
+        );
+    }
+
+    #[test]
+    fn test_cancel_pending_stop() {
+        let mut engine = Engine::default();
+        engine.match_order(stop(1, 100, None, 5, IncomingSide::Sell));
+
+        let events = engine.match_order(IncomingOrder::InboundCancel(IncomingCancelOrder {
+            order_id: 1,
+            symbol: 0,
+        }));
+        match &events[0] {
+            BookEvent::Cancel(cancel) => assert_eq!(cancel.qty, 5),
+            _ => panic!("Expected CancelEvent"),
+        }
+        assert!(engine.get_stops().is_empty());
+    }
+
+    #[test]
+    fn test_stp_cancel_newest() {
+        let mut engine = Engine::default();
+        engine.set_stp_mode(Some(StpMode::CancelNewest));
+        engine.match_order(owned(1, 100, 5, IncomingSide::Sell, 7));
+
+        let events = engine.match_order(owned(2, 100, 5, IncomingSide::Buy, 7));
+
+        // Taker is cancelled, nothing trades or rests
+        assert_eq!(events.len(), 1);
+        let stp = self_trade(&events[0]);
+        assert_eq!(stp.mode, StpMode::CancelNewest);
+        assert_eq!((stp.maker_qty, stp.taker_qty), (0, 5));
+        assert_eq!(engine.get_book().get_order(1).unwrap().qty, 5);
+        assert!(engine.get_book().get_order(2).is_none());
+    }
+
+    #[test]
+    fn test_stp_cancel_oldest_keeps_matching() {
+        let mut engine = Engine::default();
+        engine.set_stp_mode(Some(StpMode::CancelOldest));
+        engine.match_order(owned(1, 100, 5, IncomingSide::Sell, 7));
+        engine.match_order(owned(2, 100, 5, IncomingSide::Sell, 8));
+
+        let events = engine.match_order(owned(3, 100, 4, IncomingSide::Buy, 7));
+
+        assert_eq!(events.len(), 2);
+        let stp = self_trade(&events[0]);
+        assert_eq!(stp.maker, 1);
+        assert_eq!((stp.maker_qty, stp.taker_qty), (5, 0));
+        match &events[1] {
+            BookEvent::Match(fill) => assert_eq!((fill.maker, fill.qty), (2, 4)),
+            _ => panic!("Expected MatchEvent"),
+        }
+        assert!(engine.get_book().get_order(1).is_none());
+    }
+
+    #[test]
+    fn test_stp_cancel_both() {
+        let mut engine = Engine::default();
+        engine.set_stp_mode(Some(StpMode::CancelBoth));
+        engine.match_order(owned(1, 100, 5, IncomingSide::Sell, 7));
+
+        let events = engine.match_order(owned(2, 100, 8, IncomingSide::Buy, 7));
+
+        assert_eq!(events.len(), 1);
+        let stp = self_trade(&events[0]);
+        assert_eq!((stp.maker_qty, stp.taker_qty), (5, 8));
+        assert!(engine.get_book().best_ask().is_none());
+        assert!(engine.get_book().best_bid().is_none());
+    }
+
+    #[test]
+    fn test_stp_decrement_and_cancel() {
+        let mut engine = Engine::default();
+        engine.set_stp_mode(Some(StpMode::DecrementAndCancel));
+        engine.match_order(owned(1, 100, 5, IncomingSide::Sell, 7));
+
+        let events = engine.match_order(owned(2, 100, 8, IncomingSide::Buy, 7));
+
+        // Both lose 5, the maker is gone and the taker rests the other 3
+        assert_eq!(events.len(), 2);
+        let stp = self_trade(&events[0]);
+        assert_eq!((stp.maker_qty, stp.taker_qty), (5, 5));
+        assert!(matches!(events[1], BookEvent::Insert(_)));
+        assert!(engine.get_book().get_order(1).is_none());
+        assert_eq!(engine.get_book().get_order(2).unwrap().qty, 3);
+    }
+
+    #[test]
+    fn test_fok_ignores_own_liquidity_under_stp() {
+        let mut engine = Engine::default();
+        engine.set_stp_mode(Some(StpMode::CancelOldest));
+        engine.match_order(limit(1, 100, 5, IncomingSide::Sell));
+        engine.match_order(owned(2, 100, 5, IncomingSide::Sell, 7));
+
+        // Only 5 of the 10 belong to another account, nothing trades
+        let events = engine.match_order(with_tif(3, 100, 10, IncomingSide::Buy, TimeInForce::Fok));
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], BookEvent::Kill(kill) if kill.qty == 10));
+        assert_eq!(engine.get_book().get_order(1).unwrap().qty, 5);
+        assert_eq!(engine.get_book().get_order(2).unwrap().qty, 5);
+
+        // Under CancelNewest the taker stops at its own order at the head of 100
+        engine.set_stp_mode(Some(StpMode::CancelNewest));
+        engine.match_order(owned(4, 101, 10, IncomingSide::Sell, 7));
+        let events = engine.match_order(with_tif(5, 101, 6, IncomingSide::Buy, TimeInForce::Fok));
+        assert!(matches!(&events[0], BookEvent::Kill(kill) if kill.qty == 6));
+
+        // Other accounts' liquidity ahead of the own order is usable, nothing behind it is
+        engine.match_order(IncomingOrder::InboundCancel(IncomingCancelOrder {
+            order_id: 1,
+            symbol: DEFAULT_SYMBOL,
+        }));
+        engine.match_order(limit(6, 101, 5, IncomingSide::Sell));
+        engine.match_order(owned(7, 101, 5, IncomingSide::Sell, 7));
+        let events = engine.match_order(with_tif(8, 101, 16, IncomingSide::Buy, TimeInForce::Fok));
+        assert!(matches!(&events[0], BookEvent::Kill(kill) if kill.qty == 16));
+        let events = engine.match_order(with_tif(9, 101, 15, IncomingSide::Buy, TimeInForce::Fok));
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| matches!(e, BookEvent::Match(_))));
+    }
+
+    #[test]
+    fn test_stp_disabled_by_default() {
+        let mut engine = Engine::default();
+        engine.match_order(owned(1, 100, 5, IncomingSide::Sell, 7));
+
+        let events = engine.match_order(owned(2, 100, 5, IncomingSide::Buy, 7));
+        assert!(matches!(events[0], BookEvent::Match(_)));
+    }
+
+    fn reject_reason(event: &BookEvent) -> RejectReason {
+        match event {
+            BookEvent::Reject(reject) => reject.reason,
+            _ => panic!("Expected RejectEvent"),
+        }
+    }
+
+    #[test]
+    fn test_reject_unknown_order_id() {
+        let mut engine = Engine::default();
+
+        let events = engine.match_order(IncomingOrder::InboundCancel(IncomingCancelOrder {
+            order_id: 42,
+            symbol: 0,
+        }));
+        assert_eq!(events.len(), 1);
+        assert_eq!(reject_reason(&events[0]), RejectReason::UnknownOrderId);
+
+        let events = engine.match_order(IncomingOrder::InboundModify(IncomingModifyOrder {
+            order_id: 42,
+            price: 100,
+            qty: 5,
+            symbol: 0,
+        }));
+        assert_eq!(reject_reason(&events[0]), RejectReason::UnknownOrderId);
+    }
+
+    #[test]
+    fn test_reject_zero_quantity_and_price() {
+        let mut engine = Engine::default();
+
+        let events = engine.match_order(limit(1, 100, 0, IncomingSide::Buy));
+        assert_eq!(reject_reason(&events[0]), RejectReason::ZeroQuantity);
+
+        let events = engine.match_order(limit(2, 0, 5, IncomingSide::Buy));
+        assert_eq!(reject_reason(&events[0]), RejectReason::ZeroPrice);
+
+        assert!(engine.get_book().best_bid().is_none());
+    }
+
+    #[test]
+    fn test_reject_market_without_liquidity() {
+        let mut engine = Engine::default();
+        engine.match_order(limit(1, 100, 5, IncomingSide::Buy));
+
+        let events = engine.match_order(market(2, 5, IncomingSide::Buy));
+        assert_eq!(events.len(), 1);
+        assert_eq!(reject_reason(&events[0]), RejectReason::NoLiquidity);
+    }
+
+    #[test]
+    fn test_reject_invalid_input() {
+        let mut engine = Engine::default();
+
+        let events = engine.match_order(IncomingOrder::InboundInvalid(IncomingInvalidOrder {
+            order_id: Some(7),
+            reason: RejectReason::InvalidSide,
+            symbol: 0,
+        }));
+        match &events[0] {
+            BookEvent::Reject(reject) => {
+                assert_eq!(reject.order_id, Some(7));
+                assert_eq!(reject.reason, RejectReason::InvalidSide);
+            }
+            _ => panic!("Expected RejectEvent"),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_live_id_rejected() {
+        let mut engine = Engine::default();
+        engine.match_order(limit(1, 100, 5, IncomingSide::Buy));
+
+        let events = engine.match_order(limit(1, 99, 7, IncomingSide::Buy));
+        assert_eq!(events.len(), 1);
+        assert_eq!(reject_reason(&events[0]), RejectReason::DuplicateOrderId);
+
+        // Original order is untouched and nothing was orphaned
+        assert_eq!(engine.get_book().len(), 1);
+        assert_eq!(engine.get_book().get_order(1).unwrap().price, 100);
+
+        // Live stop ids count too
+        engine.match_order(stop(2, 150, None, 5, IncomingSide::Buy));
+        let events = engine.match_order(limit(2, 99, 7, IncomingSide::Buy));
+        assert_eq!(reject_reason(&events[0]), RejectReason::DuplicateOrderId);
+
+        // Cancel removes the order from both slab and map
+        engine.match_order(IncomingOrder::InboundCancel(IncomingCancelOrder {
+            order_id: 1,
+            symbol: 0,
+        }));
+        assert!(engine.get_book().is_empty());
+        assert!(engine.get_book().get_order(1).is_none());
+
+        // Id is free again once the order is gone
+        let events = engine.match_order(limit(1, 98, 5, IncomingSide::Buy));
+        assert!(matches!(events[0], BookEvent::Insert(_)));
+        assert_eq!(engine.get_book().len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_session_id_rejected() {
+        let mut engine = Engine::default();
+        engine.set_duplicate_id_policy(DuplicateIdPolicy::Session);
+
+        engine.match_order(limit(1, 100, 5, IncomingSide::Sell));
+        engine.match_order(market(2, 5, IncomingSide::Buy));
+        assert!(engine.get_book().is_empty());
+
+        // Both ids are gone from the book but were seen this session
+        let events = engine.match_order(limit(1, 100, 5, IncomingSide::Sell));
+        assert_eq!(reject_reason(&events[0]), RejectReason::DuplicateOrderId);
+        let events = engine.match_order(limit(2, 100, 5, IncomingSide::Sell));
+        assert_eq!(reject_reason(&events[0]), RejectReason::DuplicateOrderId);
+        assert!(engine.get_book().is_empty());
+    }
+
+    #[test]
+    fn test_submit_sequences_events() {
+        let mut engine = Engine::default();
+
+        let first = engine.submit(limit(1, 100, 5, IncomingSide::Sell));
+        let second = engine.submit(limit(2, 100, 3, IncomingSide::Sell));
+        let third = engine.submit(market(3, 7, IncomingSide::Buy));
+
+        let keys: Vec<(u64, u64, u32)> = first
+            .iter()
+            .chain(&second)
+            .chain(&third)
+            .map(|entry| (entry.seq, entry.input_seq, entry.batch_index))
+            .collect();
+
+        assert_eq!(keys, vec![(1, 1, 0), (2, 2, 0), (3, 3, 0), (4, 3, 1)]);
+
+        let snapshot = engine.snapshot(false);
+        assert_eq!(snapshot[0].seq, 5);
+        assert_eq!(snapshot[0].input_seq, 0);
+    }
+
+    fn on(symbol: SymbolId, mut order: IncomingOrder) -> IncomingOrder {
+        match &mut order {
+            IncomingOrder::InboundLimit(order) => order.symbol = symbol,
+            IncomingOrder::InboundMarket(order) => order.symbol = symbol,
+            IncomingOrder::InboundStop(order) => order.symbol = symbol,
+            _ => unreachable!(),
+        }
+        order
+    }
+
+    #[test]
+    fn test_markets_match_independently() {
+        let mut engine = Engine::with_symbols(16, [1, 2]);
+
+        engine.submit(on(1, limit(1, 100, 5, IncomingSide::Sell)));
+        engine.submit(on(2, stop(2, 100, None, 3, IncomingSide::Buy)));
+
+        // Same price on another symbol does not cross
+        let events = engine.submit(on(2, limit(3, 100, 5, IncomingSide::Buy)));
+        assert!(matches!(
+            events[..],
+            [EventEnvelope {
+                symbol: 2,
+                event: BookEvent::Insert(_),
+                ..
+            }]
+        ));
+
+        // Trades on symbol 1 do not trigger stops on symbol 2
+        let events = engine.submit(on(1, market(4, 2, IncomingSide::Buy)));
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            events[0],
+            EventEnvelope {
+                symbol: 1,
+                event: BookEvent::Match(_),
+                ..
+            }
+        ));
+        assert_eq!(engine.market(2).unwrap().get_stops().len(), 1);
+        assert_eq!(engine.market(1).unwrap().last_trade_price(), Some(100));
+        assert_eq!(engine.market(2).unwrap().last_trade_price(), None);
+
+        // Ids are only live within their own market
+        let events = engine.submit(on(2, limit(1, 90, 5, IncomingSide::Buy)));
+        assert!(matches!(events[0].event, BookEvent::Insert(_)));
+
+        let events = engine.submit(on(3, limit(5, 100, 5, IncomingSide::Buy)));
+        assert_eq!(events[0].symbol, 3);
+        assert_eq!(reject_reason(&events[0].event), RejectReason::UnknownSymbol);
+
+        let snapshots = engine.snapshot(false);
+        let symbols: Vec<SymbolId> = snapshots.iter().map(|entry| entry.symbol).collect();
+        assert_eq!(symbols, vec![1, 2]);
+        match &snapshots[1].event {
+            BookEvent::BookSnapshot(snapshot) => {
+                assert_eq!(snapshot.symbol, 2);
+                assert_eq!(
+                    snapshot.checksum,
+                    engine.market(2).unwrap().get_book().checksum()
+                );
+            }
+            _ => panic!("Expected BookSnapshot"),
+        }
+    }
+
+    #[test]
+    fn test_instrument_spec_rejects() {
+        let mut engine = Engine::with_symbols(16, [1, 2]);
+        assert!(engine.set_instrument_spec(InstrumentSpec {
+            symbol: 1,
+            tick_size: 5,
+            lot_size: 10,
+            ..InstrumentSpec::default()
+        }));
+        assert!(!engine.set_instrument_spec(InstrumentSpec {
+            symbol: 3,
+            ..InstrumentSpec::default()
+        }));
+
+        let events = engine.match_order(on(1, limit(1, 101, 10, IncomingSide::Buy)));
+        assert_eq!(reject_reason(&events[0]), RejectReason::PriceNotOnTick);
+        assert!(engine.market(1).unwrap().get_book().best_bid().is_none());
+
+        let events = engine.match_order(on(1, market(2, 15, IncomingSide::Buy)));
+        assert_eq!(reject_reason(&events[0]), RejectReason::QtyNotOnLot);
+
+        // Other markets keep the permissive default
+        let events = engine.match_order(on(2, limit(3, 101, 15, IncomingSide::Buy)));
+        assert!(matches!(events[0], BookEvent::Insert(_)));
+
+        engine.match_order(on(1, limit(4, 100, 10, IncomingSide::Buy)));
+        let events = engine.match_order(IncomingOrder::InboundModify(IncomingModifyOrder {
+            order_id: 4,
+            price: 100,
+            qty: 5,
+            symbol: 1,
+        }));
+        assert_eq!(reject_reason(&events[0]), RejectReason::QtyNotOnLot);
+    }
+
+    #[test]
+    fn test_price_band_rejects_limits_outside() {
+        let mut engine = Engine::new(16);
+        engine.set_price_band(Some(PriceBand {
+            reference: BandReference::LastTrade,
+            width_bps: 1_000,
+        }));
+
+        // No trade yet, nothing is banded
+        engine.match_order(limit(1, 100, 5, IncomingSide::Sell));
+        engine.match_order(limit(2, 100, 1, IncomingSide::Buy));
+        assert_eq!(engine.get_book().best_ask(), Some(&PriceKey(100)));
+
+        let events = engine.match_order(limit(3, 111, 1, IncomingSide::Buy));
+        assert_eq!(reject_reason(&events[0]), RejectReason::PriceOutsideBand);
+        let events = engine.match_order(limit(4, 89, 1, IncomingSide::Sell));
+        assert_eq!(reject_reason(&events[0]), RejectReason::PriceOutsideBand);
+
+        // Both edges are inside
+        let events = engine.match_order(limit(5, 110, 1, IncomingSide::Buy));
+        assert!(matches!(events[0], BookEvent::Match(_)));
+        let events = engine.match_order(limit(6, 90, 1, IncomingSide::Buy));
+        assert!(matches!(events[0], BookEvent::Insert(_)));
+
+        // Repricing amends are banded too
+        let events = engine.match_order(IncomingOrder::InboundModify(IncomingModifyOrder {
+            order_id: 6,
+            price: 89,
+            qty: 1,
+            symbol: DEFAULT_SYMBOL,
+        }));
+        assert_eq!(reject_reason(&events[0]), RejectReason::PriceOutsideBand);
+    }
+
+    #[test]
+    fn test_price_band_stops_market_sweep() {
+        let mut engine = Engine::new(16);
+        engine.match_order(limit(1, 100, 1, IncomingSide::Sell));
+        engine.match_order(limit(2, 100, 1, IncomingSide::Buy));
+        engine.match_order(limit(3, 104, 5, IncomingSide::Sell));
+        engine.match_order(limit(4, 110, 5, IncomingSide::Sell));
+        engine.set_price_band(Some(PriceBand {
+            reference: BandReference::LastTrade,
+            width_bps: 500,
+        }));
+
+        let events = engine.match_order(market(5, 8, IncomingSide::Buy));
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], BookEvent::Match(fill) if fill.price == 104 && fill.qty == 5));
+        match &events[1] {
+            BookEvent::BandCancel(cancel) => {
+                assert_eq!(cancel.qty, 3);
+                assert_eq!(cancel.band_price, 105);
+            }
+            _ => panic!("Expected BandCancelEvent"),
+        }
+        assert_eq!(engine.get_book().best_ask(), Some(&PriceKey(110)));
+
+        // Band follows the mid: bid 100 and ask 110 put it at 100..=110
+        engine.match_order(limit(6, 100, 5, IncomingSide::Buy));
+        engine.set_price_band(Some(PriceBand {
+            reference: BandReference::Mid,
+            width_bps: 500,
+        }));
+        let events = engine.match_order(limit(7, 99, 1, IncomingSide::Buy));
+        assert_eq!(reject_reason(&events[0]), RejectReason::PriceOutsideBand);
+        let events = engine.match_order(market(8, 8, IncomingSide::Buy));
+        assert!(matches!(&events[0], BookEvent::Match(fill) if fill.price == 110));
     }
 }
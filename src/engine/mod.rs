@@ -1 +1,6 @@
+pub mod clock;
+pub mod instrument;
+pub mod market;
 pub mod matching_engine;
+pub mod price_band;
+pub mod symbols;
@@ -0,0 +1,70 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Price a band is centred on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BandReference {
+    #[default]
+    LastTrade,
+    Mid, // Midpoint of the best bid and ask
+}
+
+impl FromStr for BandReference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "last-trade" => Ok(BandReference::LastTrade),
+            "mid" => Ok(BandReference::Mid),
+            other => Err(format!("Unknown band reference: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for BandReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BandReference::LastTrade => write!(f, "last-trade"),
+            BandReference::Mid => write!(f, "mid"),
+        }
+    }
+}
+
+/// Fat-finger protection: how far from the reference price orders may trade or rest
+///
+/// Limit orders priced outside the band are rejected, market orders stop sweeping at its
+/// edge. Without a reference price (no trade yet, or a one-sided book for `Mid`) nothing
+/// is banded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceBand {
+    pub reference: BandReference,
+    pub width_bps: u32, // Half-width in basis points of the reference price
+}
+
+impl PriceBand {
+    /// Lowest and highest price inside the band around `reference`, both inclusive
+    pub fn bounds(&self, reference: u64) -> (u64, u64) {
+        let offset = (reference as u128 * self.width_bps as u128 / 10_000) as u64;
+        (
+            reference.saturating_sub(offset),
+            reference.saturating_add(offset),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounds() {
+        let band = PriceBand {
+            reference: BandReference::LastTrade,
+            width_bps: 500,
+        };
+        assert_eq!(band.bounds(10_000), (9_500, 10_500));
+        assert_eq!(band.bounds(10), (10, 10));
+        assert_eq!(band.bounds(u64::MAX).1, u64::MAX);
+        assert_eq!("mid".parse(), Ok(BandReference::Mid));
+    }
+}
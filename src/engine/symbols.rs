@@ -0,0 +1,141 @@
+//! Markets the engine runs, loaded from a TOML config
+//!
+//! ```toml
+//! [[symbols]]
+//! id = 1
+//! name = "BTC-PERP"
+//!
+//! [[symbols]]
+//! id = 2
+//! name = "ETH-PERP"
+//! ```
+//!
+//! Without a config the engine runs a single market, `DEFAULT_SYMBOL`.
+
+use crate::data::order_types::{DEFAULT_SYMBOL, SymbolId};
+use crate::storage::codec::invalid_data;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SymbolConfig {
+    pub id: SymbolId,
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryFile {
+    symbols: Vec<SymbolConfig>,
+}
+
+/// Every configured market by id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolRegistry {
+    symbols: BTreeMap<SymbolId, SymbolConfig>,
+}
+
+impl Default for SymbolRegistry {
+    fn default() -> Self {
+        let default = SymbolConfig {
+            id: DEFAULT_SYMBOL,
+            name: "DEFAULT".to_string(),
+        };
+        Self {
+            symbols: BTreeMap::from([(DEFAULT_SYMBOL, default)]),
+        }
+    }
+}
+
+impl SymbolRegistry {
+    pub fn load(path: &str) -> io::Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parse a registry, ids and names must be unique and at least one symbol given
+    pub fn from_toml(text: &str) -> io::Result<Self> {
+        let file: RegistryFile = toml::from_str(text).map_err(|e| invalid_data(e.to_string()))?;
+        if file.symbols.is_empty() {
+            return Err(invalid_data("Symbol registry is empty".to_string()));
+        }
+
+        let mut symbols = BTreeMap::new();
+        for symbol in file.symbols {
+            if symbols
+                .values()
+                .any(|s: &SymbolConfig| s.name == symbol.name)
+            {
+                return Err(invalid_data(format!(
+                    "Duplicate symbol name: {}",
+                    symbol.name
+                )));
+            }
+            if let Some(existing) = symbols.insert(symbol.id, symbol) {
+                return Err(invalid_data(format!(
+                    "Duplicate symbol id: {}",
+                    existing.id
+                )));
+            }
+        }
+
+        Ok(Self { symbols })
+    }
+
+    /// Configured ids in ascending order
+    pub fn ids(&self) -> impl Iterator<Item = SymbolId> + '_ {
+        self.symbols.keys().copied()
+    }
+
+    pub fn get(&self, id: SymbolId) -> Option<&SymbolConfig> {
+        self.symbols.get(&id)
+    }
+
+    pub fn id_of(&self, name: &str) -> Option<SymbolId> {
+        self.symbols
+            .values()
+            .find(|symbol| symbol.name == name)
+            .map(|symbol| symbol.id)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_registry() {
+        let registry = SymbolRegistry::from_toml(
+            "[[symbols]]\nid = 7\nname = \"ETH-PERP\"\n\n[[symbols]]\nid = 1\nname = \"BTC-PERP\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(registry.ids().collect::<Vec<_>>(), vec![1, 7]);
+        assert_eq!(registry.get(7).unwrap().name, "ETH-PERP");
+        assert_eq!(registry.id_of("BTC-PERP"), Some(1));
+        assert_eq!(SymbolRegistry::default().ids().collect::<Vec<_>>(), vec![0]);
+    }
+
+    #[test]
+    fn test_invalid_registry_is_rejected() {
+        for text in [
+            "symbols = []",
+            "[[symbols]]\nid = 1\nname = \"A\"\n[[symbols]]\nid = 1\nname = \"B\"\n",
+            "[[symbols]]\nid = 1\nname = \"A\"\n[[symbols]]\nid = 2\nname = \"A\"\n",
+            "[[symbols]]\nid = 1\n",
+        ] {
+            assert!(SymbolRegistry::from_toml(text).is_err(), "{}", text);
+        }
+    }
+}
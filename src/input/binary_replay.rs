@@ -0,0 +1,184 @@
+//! Binary replay files
+//!
+//! Header: magic `MERP`, format version (u16), record length (u16), all little-endian.
+//! Then one fixed-width record per input, laid out as in `storage::order_codec`.
+
+use crate::data::order_types::IncomingOrder;
+use crate::input::source::{InputReader, open_input};
+use crate::storage::codec::invalid_data;
+use crate::storage::order_codec::{ORDER_RECORD_LEN, decode_order, encode_order};
+use std::io::{self, ErrorKind, Read, Write};
+use std::str::FromStr;
+
+pub const REPLAY_MAGIC: [u8; 4] = *b"MERP";
+pub const REPLAY_VERSION: u16 = 1;
+
+/// On-disk format of a replay file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplayFormat {
+    #[default]
+    Csv,
+    Binary,
+}
+
+impl FromStr for ReplayFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ReplayFormat::Csv),
+            "binary" => Ok(ReplayFormat::Binary),
+            other => Err(format!("Unknown replay format: {}", other)),
+        }
+    }
+}
+
+pub struct BinaryReplayWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> BinaryReplayWriter<W> {
+    /// Start a replay file, writing its header
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&REPLAY_MAGIC)?;
+        writer.write_all(&REPLAY_VERSION.to_le_bytes())?;
+        writer.write_all(&(ORDER_RECORD_LEN as u16).to_le_bytes())?;
+        Ok(Self { writer })
+    }
+
+    #[inline]
+    pub fn write(&mut self, order: &IncomingOrder) -> io::Result<()> {
+        self.writer.write_all(&encode_order(order))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub struct BinaryReplayReader<R: Read> {
+    reader: R,
+}
+
+impl BinaryReplayReader<InputReader> {
+    /// Open a replay file, `-` for stdin, decompressing gzip or zstd
+    pub fn from_file(path: &str) -> io::Result<Self> {
+        Self::new(open_input(path)?)
+    }
+}
+
+impl<R: Read> BinaryReplayReader<R> {
+    /// Check the header, fails on a wrong magic, version or record length
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+
+        if header[..4] != REPLAY_MAGIC {
+            return Err(invalid_data("Not a binary replay file".to_string()));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != REPLAY_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported replay file version: {}",
+                version
+            )));
+        }
+        let record_len = u16::from_le_bytes([header[6], header[7]]);
+        if record_len as usize != ORDER_RECORD_LEN {
+            return Err(invalid_data(format!(
+                "Unexpected replay record length: {}",
+                record_len
+            )));
+        }
+
+        Ok(Self { reader })
+    }
+
+    /// Read the next input, None at the end of the file
+    /// A record cut short is an error, unlike the journal a replay file is never torn
+    pub fn next_order(&mut self) -> io::Result<Option<IncomingOrder>> {
+        let mut record = [0u8; ORDER_RECORD_LEN];
+        let mut filled = 0;
+        while filled < ORDER_RECORD_LEN {
+            match self.reader.read(&mut record[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(invalid_data("Truncated replay record".to_string())),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        decode_order(&record).map(Some)
+    }
+
+    pub fn parse_orders(&mut self) -> io::Result<Vec<IncomingOrder>> {
+        self.collect()
+    }
+}
+
+impl<R: Read> Iterator for BinaryReplayReader<R> {
+    type Item = io::Result<IncomingOrder>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_order().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::order_types::{IncomingSide, TimeInForce};
+    use crate::data::orders::inbound_orders::{IncomingCancelOrder, IncomingLimitOrder};
+
+    fn orders() -> Vec<IncomingOrder> {
+        vec![
+            IncomingOrder::InboundLimit(IncomingLimitOrder {
+                order_id: 1,
+                price: 100,
+                qty: 5,
+                side: IncomingSide::Buy,
+                account_id: 3,
+                post_only: None,
+                time_in_force: TimeInForce::Ioc,
+                symbol: 0,
+            }),
+            IncomingOrder::InboundCancel(IncomingCancelOrder {
+                order_id: 1,
+                symbol: 0,
+            }),
+        ]
+    }
+
+    fn write(orders: &[IncomingOrder]) -> Vec<u8> {
+        let mut writer = BinaryReplayWriter::new(vec![]).unwrap();
+        for order in orders {
+            writer.write(order).unwrap();
+        }
+        writer.writer
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = write(&orders());
+        assert_eq!(bytes.len(), 8 + 2 * ORDER_RECORD_LEN);
+
+        let parsed = BinaryReplayReader::new(&bytes[..])
+            .unwrap()
+            .parse_orders()
+            .unwrap();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", orders()));
+    }
+
+    #[test]
+    fn test_bad_header_and_truncation() {
+        let mut bytes = write(&orders());
+        bytes.pop();
+        let mut reader = BinaryReplayReader::new(&bytes[..]).unwrap();
+        assert!(reader.parse_orders().is_err());
+
+        bytes[0] = b'X';
+        assert!(BinaryReplayReader::new(&bytes[..]).is_err());
+        assert!(BinaryReplayReader::new(&b"ADD,1"[..]).is_err());
+    }
+}
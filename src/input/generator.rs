@@ -1,7 +1,11 @@
-use crate::data::order_types::{IncomingOrder, IncomingSide};
+use crate::data::order_types::{
+    DEFAULT_SYMBOL, IncomingOrder, IncomingSide, PostOnlyMode, SymbolId, TimeInForce,
+};
 use crate::data::orders::inbound_orders::{
     IncomingCancelOrder, IncomingLimitOrder, IncomingMarketOrder,
 };
+use crate::data::orders::resting_orders::AccountId;
+use crate::input::binary_replay::{BinaryReplayWriter, ReplayFormat};
 use rand::rngs::StdRng;
 use rand::{RngExt, SeedableRng};
 use std::fs::File;
@@ -17,13 +21,29 @@ pub struct Generator {
     market_ratio: f64,
     cancel_ratio: f64,
     max_qty: u32,
-    active_orders: Vec<u64>,
-    replay_writer: BufWriter<File>, // <-- write events to file
+    active_orders: Vec<(u64, SymbolId)>,
+    symbols: Vec<SymbolId>,    // Markets orders are spread over, uniformly
+    replay_writer: ReplaySink, // <-- write events to file
+}
+
+/// Replay file the generated events are written to
+enum ReplaySink {
+    Csv(BufWriter<File>),
+    Binary(BinaryReplayWriter<BufWriter<File>>),
 }
 
 impl Generator {
-    pub fn new(seed: u64, start_mid: i64, replay_path: &str) -> std::io::Result<Self> {
-        let file = File::create(replay_path)?;
+    pub fn new(
+        seed: u64,
+        start_mid: i64,
+        replay_path: &str,
+        format: ReplayFormat,
+    ) -> std::io::Result<Self> {
+        let file = BufWriter::new(File::create(replay_path)?);
+        let replay_writer = match format {
+            ReplayFormat::Csv => ReplaySink::Csv(file),
+            ReplayFormat::Binary => ReplaySink::Binary(BinaryReplayWriter::new(file)?),
+        };
         Ok(Self {
             rng: StdRng::seed_from_u64(seed),
             next_order_id: 1,
@@ -35,20 +55,44 @@ impl Generator {
             cancel_ratio: 0.05,
             max_qty: 1 << 20,
             active_orders: Vec::new(),
-            replay_writer: BufWriter::new(file),
+            symbols: vec![DEFAULT_SYMBOL],
+            replay_writer,
         })
     }
 
+    /// Spread new orders over these symbols instead of only `DEFAULT_SYMBOL`
+    pub fn set_symbols(&mut self, symbols: Vec<SymbolId>) {
+        assert!(!symbols.is_empty(), "Generator needs at least one symbol");
+        self.symbols = symbols;
+    }
+
+    /// Symbol for a new order, drawing from the rng only with several symbols
+    /// so single-market runs stay identical for a given seed
+    fn next_symbol(&mut self) -> SymbolId {
+        match self.symbols.len() {
+            1 => self.symbols[0],
+            n => self.symbols[self.rng.random_range(0..n)],
+        }
+    }
+
     fn update_mid(&mut self) {
         let shift = self.rng.random_range(-self.volatility..=self.volatility);
         self.mid_price = (self.mid_price + shift).max(1);
     }
 
     fn write_event(&mut self, event: &IncomingOrder) {
+        let writer = match &mut self.replay_writer {
+            ReplaySink::Csv(writer) => writer,
+            ReplaySink::Binary(writer) => {
+                let _ = writer.write(event);
+                return;
+            }
+        };
+
         let line = match event {
             IncomingOrder::InboundLimit(order) => {
                 format!(
-                    "ADD,{},{},LIMIT,{},{}\n",
+                    "ADD,{},{},LIMIT,{},{}{}\n",
                     order.order_id,
                     match order.side {
                         IncomingSide::Buy => "B",
@@ -56,113 +100,254 @@ impl Generator {
                     },
                     order.price,
                     order.qty,
+                    limit_flags(order),
                 )
             }
             IncomingOrder::InboundMarket(order) => {
                 format!(
-                    "ADD,{},{},MARKET,{}\n",
+                    "ADD,{},{},MARKET,{}{}{}\n",
+                    order.order_id,
+                    match order.side {
+                        IncomingSide::Buy => "B",
+                        IncomingSide::Sell => "A",
+                    },
+                    order.qty,
+                    account_flag(order.account_id),
+                    symbol_flag(order.symbol),
+                )
+            }
+            IncomingOrder::InboundIceberg(order) => {
+                format!(
+                    "ADD,{},{},ICEBERG,{},{},{}{}{}\n",
                     order.order_id,
                     match order.side {
                         IncomingSide::Buy => "B",
                         IncomingSide::Sell => "A",
                     },
+                    order.price,
+                    order.display_qty,
+                    order.total_qty,
+                    account_flag(order.account_id),
+                    symbol_flag(order.symbol),
+                )
+            }
+            IncomingOrder::InboundStop(order) => {
+                let side = match order.side {
+                    IncomingSide::Buy => "B",
+                    IncomingSide::Sell => "A",
+                };
+                match order.limit_price {
+                    Some(limit_price) => format!(
+                        "ADD,{},{},STOP_LIMIT,{},{},{}{}{}\n",
+                        order.order_id,
+                        side,
+                        order.trigger_price,
+                        limit_price,
+                        order.qty,
+                        account_flag(order.account_id),
+                        symbol_flag(order.symbol),
+                    ),
+                    None => format!(
+                        "ADD,{},{},STOP,{},{}{}{}\n",
+                        order.order_id,
+                        side,
+                        order.trigger_price,
+                        order.qty,
+                        account_flag(order.account_id),
+                        symbol_flag(order.symbol),
+                    ),
+                }
+            }
+            IncomingOrder::InboundModify(order) => {
+                format!(
+                    "MODIFY,{},{},{}{}\n",
+                    order.order_id,
+                    order.price,
                     order.qty,
+                    symbol_flag(order.symbol),
                 )
             }
             IncomingOrder::InboundCancel(order) => {
-                format!("CANCEL,{}\n", order.order_id,)
+                format!("CANCEL,{}{}\n", order.order_id, symbol_flag(order.symbol))
+            }
+            IncomingOrder::InboundTime(time) => {
+                format!("TIME,{}\n", time.ts)
             }
+            // Never generated and has no replay line
+            IncomingOrder::InboundInvalid(_) => return,
         };
 
-        let _ = self.replay_writer.write_all(line.as_bytes());
+        let _ = writer.write_all(line.as_bytes());
     }
 
     pub fn generate(&mut self, num_events: usize) -> Vec<IncomingOrder> {
-        let mut inputs = vec![];
-        for _ in 0..num_events {
-            self.update_mid();
-
-            let order_id = self.next_order_id;
-
-            let side = if self.rng.random_bool(0.5) {
-                IncomingSide::Buy
-            } else {
-                IncomingSide::Sell
-            };
-
-            let qty = self.rng.random_range(1..=self.max_qty);
+        (0..num_events).map(|_| self.next_event()).collect()
+    }
 
-            let roll: f64 = self.rng.random_range(0.0..1.0);
+    /// Generate one event and append it to the replay file
+    pub fn next_event(&mut self) -> IncomingOrder {
+        self.update_mid();
 
-            // Generate cancel order
-            if roll < self.cancel_ratio && !self.active_orders.is_empty() {
-                let idx = self.rng.random_range(0..self.active_orders.len());
-                let remove_order_id = self.active_orders.swap_remove(idx);
-                let event = IncomingOrder::InboundCancel(IncomingCancelOrder {
-                    order_id: remove_order_id,
-                });
-                self.write_event(&event);
-                inputs.push(event);
-                continue;
-            }
+        let order_id = self.next_order_id;
 
-            self.next_order_id += 1;
-
-            // Generate market order
-            if roll < self.market_ratio {
-                let event = IncomingOrder::InboundMarket(IncomingMarketOrder {
-                    order_id,
-                    side,
-                    qty,
-                });
-                self.write_event(&event);
-                inputs.push(event);
-                continue;
-            }
+        let side = if self.rng.random_bool(0.5) {
+            IncomingSide::Buy
+        } else {
+            IncomingSide::Sell
+        };
 
-            // Aggressive limit order (cross spread)
-            if roll < self.market_ratio + self.cross_ratio {
-                let price = match side {
-                    IncomingSide::Buy => self.mid_price + self.spread,
-                    IncomingSide::Sell => self.mid_price - self.spread,
-                };
+        let qty = self.rng.random_range(1..=self.max_qty);
 
-                self.active_orders.push(order_id);
+        let roll: f64 = self.rng.random_range(0.0..1.0);
 
-                let event = IncomingOrder::InboundLimit(IncomingLimitOrder {
-                    order_id,
-                    side,
-                    price: price as u64,
-                    qty,
-                });
+        // Generate cancel order
+        if roll < self.cancel_ratio && !self.active_orders.is_empty() {
+            let idx = self.rng.random_range(0..self.active_orders.len());
+            let (remove_order_id, symbol) = self.active_orders.swap_remove(idx);
+            let event = IncomingOrder::InboundCancel(IncomingCancelOrder {
+                order_id: remove_order_id,
+                symbol,
+            });
+            self.write_event(&event);
+            return event;
+        }
 
-                self.write_event(&event);
-                inputs.push(event);
-                continue;
-            }
+        self.next_order_id += 1;
+        let symbol = self.next_symbol();
 
-            // Passive limit order (around mid price)
-            let distance = self.rng.random_range(0..=5);
+        // Generate market order
+        if roll < self.market_ratio {
+            let event = IncomingOrder::InboundMarket(IncomingMarketOrder {
+                order_id,
+                side,
+                account_id: 0,
+                qty,
+                symbol,
+            });
+            self.write_event(&event);
+            return event;
+        }
 
+        // Aggressive limit order (cross spread)
+        if roll < self.market_ratio + self.cross_ratio {
             let price = match side {
-                IncomingSide::Buy => self.mid_price - distance,
-                IncomingSide::Sell => self.mid_price + distance,
-            }
-            .max(1);
+                IncomingSide::Buy => self.mid_price + self.spread,
+                IncomingSide::Sell => self.mid_price - self.spread,
+            };
 
-            self.active_orders.push(order_id);
+            self.active_orders.push((order_id, symbol));
 
             let event = IncomingOrder::InboundLimit(IncomingLimitOrder {
                 order_id,
                 side,
+                account_id: 0,
                 price: price as u64,
                 qty,
+                post_only: None,
+                time_in_force: TimeInForce::Gtc,
+                symbol,
             });
 
             self.write_event(&event);
-            inputs.push(event);
+            return event;
+        }
+
+        // Passive limit order (around mid price)
+        let distance = self.rng.random_range(0..=5);
+
+        let price = match side {
+            IncomingSide::Buy => self.mid_price - distance,
+            IncomingSide::Sell => self.mid_price + distance,
+        }
+        .max(1);
+
+        self.active_orders.push((order_id, symbol));
+
+        let event = IncomingOrder::InboundLimit(IncomingLimitOrder {
+            order_id,
+            side,
+            account_id: 0,
+            price: price as u64,
+            qty,
+            post_only: None,
+            time_in_force: TimeInForce::Gtc,
+            symbol,
+        });
+
+        self.write_event(&event);
+        event
+    }
+}
+
+/// Optional trailing flags of a LIMIT line, empty for a plain GTC order
+fn limit_flags(order: &IncomingLimitOrder) -> String {
+    let mut flags = String::new();
+
+    match order.post_only {
+        None => {}
+        Some(PostOnlyMode::Reject) => flags.push_str(",POST_ONLY"),
+        Some(PostOnlyMode::Slide) => flags.push_str(",POST_ONLY_SLIDE"),
+    }
+
+    if order.time_in_force != TimeInForce::Gtc {
+        flags.push_str(&format!(",{}", order.time_in_force));
+    }
+
+    flags.push_str(&account_flag(order.account_id));
+    flags.push_str(&symbol_flag(order.symbol));
+
+    flags
+}
+
+/// Trailing account flag, empty for the default account 0
+fn account_flag(account_id: AccountId) -> String {
+    if account_id == 0 {
+        String::new()
+    } else {
+        format!(",ACCOUNT:{}", account_id)
+    }
+}
+
+/// Trailing symbol flag, empty for `DEFAULT_SYMBOL`
+fn symbol_flag(symbol: SymbolId) -> String {
+    if symbol == DEFAULT_SYMBOL {
+        String::new()
+    } else {
+        format!(",SYMBOL:{}", symbol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::binary_replay::BinaryReplayReader;
+    use crate::input::replay_reader::ReplayReader;
+
+    #[test]
+    fn test_replay_file_reads_back_as_generated() {
+        let dir = std::env::temp_dir().join(format!("generator-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for format in [ReplayFormat::Csv, ReplayFormat::Binary] {
+            let path = dir.join("replay").to_string_lossy().into_owned();
+            let mut generator = Generator::new(11, 500, &path, format).unwrap();
+            generator.set_symbols(vec![0, 3]);
+            let generated: Vec<IncomingOrder> =
+                (0..1_000).map(|_| generator.next_event()).collect();
+            drop(generator);
+
+            let read: Vec<IncomingOrder> = match format {
+                ReplayFormat::Csv => ReplayReader::from_file(&path)
+                    .unwrap()
+                    .collect::<std::io::Result<_>>(),
+                ReplayFormat::Binary => BinaryReplayReader::from_file(&path)
+                    .unwrap()
+                    .collect::<std::io::Result<_>>(),
+            }
+            .unwrap();
+            assert_eq!(format!("{:?}", read), format!("{:?}", generated));
         }
 
-        inputs
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -1,2 +1,4 @@
+pub mod binary_replay;
 pub mod generator;
 pub mod replay_reader;
+pub mod source;
@@ -1,98 +1,575 @@
-use crate::data::order_types::{IncomingOrder, IncomingSide};
+use crate::data::book_event::RejectReason;
+use crate::data::order_types::{
+    DEFAULT_SYMBOL, IncomingOrder, IncomingSide, PostOnlyMode, SymbolId, TimeInForce,
+};
 use crate::data::orders::inbound_orders::{
-    IncomingCancelOrder, IncomingLimitOrder, IncomingMarketOrder,
+    IncomingCancelOrder, IncomingIcebergOrder, IncomingInvalidOrder, IncomingLimitOrder,
+    IncomingMarketOrder, IncomingModifyOrder, IncomingStopOrder, IncomingTimeUpdate,
 };
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use crate::data::orders::resting_orders::AccountId;
+use crate::input::source::{InputReader, open_input};
+use std::fmt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+/// Parse errors kept in detail for the report, later ones are only counted
+pub const REPORT_LIMIT: usize = 1000;
+
+/// What to do with a line that cannot be parsed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Stop at the first bad line with an error
+    Strict,
+    /// Pass the line on as a MALFORMED reject and record it in the report
+    #[default]
+    Lenient,
+}
+
+impl FromStr for ParseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(ParseMode::Strict),
+            "lenient" => Ok(ParseMode::Lenient),
+            other => Err(format!("Unknown parse mode: {}", other)),
+        }
+    }
+}
+
+/// A replay line that could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// 1-based line number in the replay file
+    pub line_number: usize,
+    /// The line as read, without surrounding whitespace
+    pub line: String,
+    /// Name of the field that failed, e.g. `price` or `flag`
+    pub field: &'static str,
+    /// Offending value, None when the field is missing
+    pub value: Option<String>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(
+                f,
+                "line {}: invalid {} '{}' in '{}'",
+                self.line_number, self.field, value, self.line
+            ),
+            None => write!(
+                f,
+                "line {}: missing {} in '{}'",
+                self.line_number, self.field, self.line
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Summary of the lines a lenient replay could not parse
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseReport {
+    pub lines_read: usize,
+    pub lines_skipped: usize,
+    /// The first `REPORT_LIMIT` failures
+    pub errors: Vec<ParseError>,
+}
+
+impl fmt::Display for ParseReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Lines read: {}", self.lines_read)?;
+        writeln!(f, "Lines skipped: {}", self.lines_skipped)?;
+        for error in &self.errors {
+            writeln!(f, "{}", error)?;
+        }
+        if self.lines_skipped > self.errors.len() {
+            writeln!(
+                f,
+                "... {} more not listed",
+                self.lines_skipped - self.errors.len()
+            )?;
+        }
+        Ok(())
+    }
+}
 
 pub struct ReplayReader<R: BufRead> {
     reader: R,
     buffer: String,
+    mode: ParseMode,
+    report: ParseReport,
+    finished: bool,
 }
 
-impl ReplayReader<BufReader<File>> {
-    pub fn from_file(path: &str) -> std::io::Result<Self> {
-        let file = File::open(path)?;
-        Ok(Self {
-            reader: BufReader::new(file),
+impl ReplayReader<InputReader> {
+    /// Open a replay file, `-` for stdin, decompressing gzip or zstd
+    pub fn from_file(path: &str) -> io::Result<Self> {
+        Ok(Self::new(open_input(path)?))
+    }
+
+    pub fn from_stdin() -> io::Result<Self> {
+        Self::from_file("-")
+    }
+}
+
+impl<R: BufRead> ReplayReader<R> {
+    /// Read from any buffered source, e.g. an in-memory buffer
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
             buffer: String::with_capacity(256),
-        })
-    }
-
-    pub fn parse_orders(&mut self) -> Vec<IncomingOrder> {
-        let mut inputs = vec![];
-        while self
-            .reader
-            .read_line(&mut self.buffer)
-            .expect("Unable to open replay buffer")
-            != 0
-        {
-            match parse_event(&self.buffer) {
-                Some(event) => inputs.push(event),
-                _ => {
-                    println!("Error: Invalid input in replay file");
-                }
-            };
+            mode: ParseMode::default(),
+            report: ParseReport::default(),
+            finished: false,
+        }
+    }
+
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.mode = mode;
+    }
+
+    /// Lines read and skipped so far
+    pub fn report(&self) -> &ParseReport {
+        &self.report
+    }
+
+    pub fn parse_orders(&mut self) -> io::Result<Vec<IncomingOrder>> {
+        self.collect()
+    }
+
+    /// A bad line becomes a MALFORMED reject in lenient mode, an error in strict mode
+    fn reject(&mut self, error: ParseError) -> io::Result<IncomingOrder> {
+        if self.mode == ParseMode::Strict {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+        }
+
+        let order_id = error.line.split(',').nth(1).and_then(|id| id.parse().ok());
+        let symbol = line_symbol(&error.line);
+        self.report.lines_skipped += 1;
+        if self.report.errors.len() < REPORT_LIMIT {
+            self.report.errors.push(error);
+        }
+        Ok(IncomingOrder::InboundInvalid(IncomingInvalidOrder {
+            order_id,
+            reason: RejectReason::Malformed,
+            symbol,
+        }))
+    }
+}
+
+/// Reads one input per line, on demand. Stops after the first error
+impl<R: BufRead> Iterator for ReplayReader<R> {
+    type Item = io::Result<IncomingOrder>;
 
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.finished {
             self.buffer.clear(); // Important: clear buffer for next read
+            match self.reader.read_line(&mut self.buffer) {
+                Ok(0) => self.finished = true,
+                Ok(_) => {
+                    self.report.lines_read += 1;
+                    let line = self.buffer.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+
+                    let result = match parse_event(line) {
+                        Ok(order) => Ok(order),
+                        Err(FieldError { field, value }) => self.reject(ParseError {
+                            line_number: self.report.lines_read,
+                            line: line.to_string(),
+                            field,
+                            value,
+                        }),
+                    };
+                    self.finished = result.is_err();
+                    return Some(result);
+                }
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(e));
+                }
+            }
         }
+        None
+    }
+}
+
+/// Field that failed while parsing a line, before the line is known
+struct FieldError {
+    field: &'static str,
+    value: Option<String>,
+}
 
-        inputs
+impl FieldError {
+    fn invalid(field: &'static str, value: &str) -> Self {
+        Self {
+            field,
+            value: Some(value.to_string()),
+        }
     }
 }
 
-fn parse_event(line: &str) -> Option<IncomingOrder> {
+fn next_field<'a>(
+    parts: &mut impl Iterator<Item = &'a str>,
+    field: &'static str,
+) -> Result<&'a str, FieldError> {
+    parts.next().ok_or(FieldError { field, value: None })
+}
+
+fn parse_field<'a, T: FromStr>(
+    parts: &mut impl Iterator<Item = &'a str>,
+    field: &'static str,
+) -> Result<T, FieldError> {
+    let value = next_field(parts, field)?;
+    value.parse().map_err(|_| FieldError::invalid(field, value))
+}
+
+/// Reject anything after the last field of a line
+fn end_of_line<'a>(mut parts: impl Iterator<Item = &'a str>) -> Result<(), FieldError> {
+    match parts.next() {
+        Some(extra) => Err(FieldError::invalid("trailing field", extra)),
+        None => Ok(()),
+    }
+}
+
+fn parse_event(line: &str) -> Result<IncomingOrder, FieldError> {
     let mut parts = line.trim().split(',');
 
-    match parts.next()? {
+    let order = match next_field(&mut parts, "kind")? {
         "ADD" => {
-            let order_id = parts.next()?.parse().ok()?;
+            let order_id = parse_field(&mut parts, "order id")?;
 
-            let side = match parts.next()? {
+            let side = match next_field(&mut parts, "side")? {
                 "B" => IncomingSide::Buy,
                 "A" => IncomingSide::Sell,
-                other => {
-                    println!("Unknown side encountered: {}", other);
-                    return None;
+                _ => {
+                    return Ok(IncomingOrder::InboundInvalid(IncomingInvalidOrder {
+                        order_id: Some(order_id),
+                        reason: RejectReason::InvalidSide,
+                        symbol: line_symbol(line),
+                    }));
                 }
             };
 
-            match parts.next()? {
+            match next_field(&mut parts, "order type")? {
                 "LIMIT" => {
-                    let price = parts.next()?.parse().ok()?;
-                    let qty = parts.next()?.parse().ok()?;
+                    let price = parse_field(&mut parts, "price")?;
+                    let qty = parse_field(&mut parts, "qty")?;
 
-                    Some(IncomingOrder::InboundLimit(IncomingLimitOrder {
+                    let mut order = IncomingLimitOrder {
                         order_id,
                         side,
                         price,
                         qty,
-                    }))
+                        account_id: 0,
+                        post_only: None,
+                        time_in_force: TimeInForce::Gtc,
+                        symbol: DEFAULT_SYMBOL,
+                    };
+
+                    // Optional trailing flags
+                    for flag in parts {
+                        match flag {
+                            "POST_ONLY" => order.post_only = Some(PostOnlyMode::Reject),
+                            "POST_ONLY_SLIDE" => order.post_only = Some(PostOnlyMode::Slide),
+                            "GTC" => order.time_in_force = TimeInForce::Gtc,
+                            "IOC" => order.time_in_force = TimeInForce::Ioc,
+                            "FOK" => order.time_in_force = TimeInForce::Fok,
+                            "DAY" => order.time_in_force = TimeInForce::Day,
+                            other => {
+                                if let Some(expires_at) = other.strip_prefix("GTD:") {
+                                    order.time_in_force = TimeInForce::Gtd(
+                                        expires_at
+                                            .parse()
+                                            .map_err(|_| FieldError::invalid("expiry", other))?,
+                                    );
+                                } else if let Some(account_id) = other.strip_prefix("ACCOUNT:") {
+                                    order.account_id = account_id
+                                        .parse()
+                                        .map_err(|_| FieldError::invalid("account", other))?;
+                                } else if let Some(symbol) = other.strip_prefix("SYMBOL:") {
+                                    order.symbol = symbol
+                                        .parse()
+                                        .map_err(|_| FieldError::invalid("symbol", other))?;
+                                } else {
+                                    return Err(FieldError::invalid("flag", other));
+                                }
+                            }
+                        }
+                    }
+
+                    return Ok(IncomingOrder::InboundLimit(order));
                 }
                 "MARKET" => {
-                    let qty = parts.next()?.parse().ok()?;
+                    let qty = parse_field(&mut parts, "qty")?;
+
+                    let tags = parse_tags(&mut parts)?;
+
+                    IncomingOrder::InboundMarket(IncomingMarketOrder {
+                        order_id,
+                        side,
+                        account_id: tags.account_id,
+                        qty,
+                        symbol: tags.symbol,
+                    })
+                }
+                "ICEBERG" => {
+                    let price = parse_field(&mut parts, "price")?;
+                    let display_qty = parse_field(&mut parts, "display qty")?;
+                    let total_qty = parse_field(&mut parts, "total qty")?;
+
+                    let tags = parse_tags(&mut parts)?;
+
+                    IncomingOrder::InboundIceberg(IncomingIcebergOrder {
+                        order_id,
+                        side,
+                        account_id: tags.account_id,
+                        price,
+                        display_qty,
+                        total_qty,
+                        symbol: tags.symbol,
+                    })
+                }
+                "STOP" => {
+                    let trigger_price = parse_field(&mut parts, "trigger price")?;
+                    let qty = parse_field(&mut parts, "qty")?;
+
+                    let tags = parse_tags(&mut parts)?;
+
+                    IncomingOrder::InboundStop(IncomingStopOrder {
+                        order_id,
+                        side,
+                        account_id: tags.account_id,
+                        trigger_price,
+                        limit_price: None,
+                        qty,
+                        symbol: tags.symbol,
+                    })
+                }
+                "STOP_LIMIT" => {
+                    let trigger_price = parse_field(&mut parts, "trigger price")?;
+                    let limit_price = parse_field(&mut parts, "limit price")?;
+                    let qty = parse_field(&mut parts, "qty")?;
+
+                    let tags = parse_tags(&mut parts)?;
 
-                    Some(IncomingOrder::InboundMarket(IncomingMarketOrder {
+                    IncomingOrder::InboundStop(IncomingStopOrder {
                         order_id,
                         side,
+                        account_id: tags.account_id,
+                        trigger_price,
+                        limit_price: Some(limit_price),
                         qty,
-                    }))
+                        symbol: tags.symbol,
+                    })
                 }
-                _ => None,
+                other => return Err(FieldError::invalid("order type", other)),
             }
         }
 
+        "MODIFY" => {
+            let order_id = parse_field(&mut parts, "order id")?;
+            let price = parse_field(&mut parts, "price")?;
+            let qty = parse_field(&mut parts, "qty")?;
+
+            IncomingOrder::InboundModify(IncomingModifyOrder {
+                order_id,
+                price,
+                qty,
+                symbol: parse_symbol(&mut parts)?,
+            })
+        }
+
         "CANCEL" => {
-            let order_id = parts.next()?.parse().ok()?;
+            let order_id = parse_field(&mut parts, "order id")?;
 
-            Some(IncomingOrder::InboundCancel(IncomingCancelOrder {
+            IncomingOrder::InboundCancel(IncomingCancelOrder {
                 order_id,
-            }))
+                symbol: parse_symbol(&mut parts)?,
+            })
+        }
+
+        "TIME" => {
+            let ts = parse_field(&mut parts, "timestamp")?;
+
+            IncomingOrder::InboundTime(IncomingTimeUpdate { ts })
         }
 
-        other => {
-            println!("Unknown order_type encountered: {}", other);
-            None
+        other => return Err(FieldError::invalid("kind", other)),
+    };
+
+    // Nothing else may follow
+    end_of_line(parts)?;
+    Ok(order)
+}
+
+/// Optional trailing flags of a non-LIMIT order
+#[derive(Default)]
+struct Tags {
+    account_id: AccountId,
+    symbol: SymbolId,
+}
+
+/// Optional trailing `ACCOUNT:<id>` and `SYMBOL:<id>` flags, in any order, 0 if absent
+fn parse_tags<'a>(parts: &mut impl Iterator<Item = &'a str>) -> Result<Tags, FieldError> {
+    let mut tags = Tags::default();
+    for flag in parts {
+        if let Some(account_id) = flag.strip_prefix("ACCOUNT:") {
+            tags.account_id = account_id
+                .parse()
+                .map_err(|_| FieldError::invalid("account", flag))?;
+        } else if let Some(symbol) = flag.strip_prefix("SYMBOL:") {
+            tags.symbol = symbol
+                .parse()
+                .map_err(|_| FieldError::invalid("symbol", flag))?;
+        } else {
+            return Err(FieldError::invalid("flag", flag));
+        }
+    }
+    Ok(tags)
+}
+
+/// Optional trailing `SYMBOL:<id>` flag, `DEFAULT_SYMBOL` if absent
+fn parse_symbol<'a>(parts: &mut impl Iterator<Item = &'a str>) -> Result<SymbolId, FieldError> {
+    match parts.next() {
+        Some(flag) => flag
+            .strip_prefix("SYMBOL:")
+            .and_then(|symbol| symbol.parse().ok())
+            .ok_or_else(|| FieldError::invalid("symbol", flag)),
+        None => Ok(DEFAULT_SYMBOL),
+    }
+}
+
+/// Symbol of a line that could not be fully parsed, so its reject reaches the right market
+///
+/// Takes the first readable `SYMBOL:<id>` field, `DEFAULT_SYMBOL` if there is none.
+fn line_symbol(line: &str) -> SymbolId {
+    line.split(',')
+        .find_map(|field| field.strip_prefix("SYMBOL:")?.parse().ok())
+        .unwrap_or(DEFAULT_SYMBOL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLAY: &str = "ADD,1,B,LIMIT,100,5\n\nADD,2,A,LIMIT,abc,5\nCANCEL,1\nADD,3,B,MARKET\n";
+
+    #[test]
+    fn test_lenient_reports_bad_lines() {
+        let mut reader = ReplayReader::new(REPLAY.as_bytes());
+        let orders = reader.parse_orders().unwrap();
+        assert_eq!(orders.len(), 4);
+        assert!(matches!(
+            orders[1],
+            IncomingOrder::InboundInvalid(IncomingInvalidOrder {
+                order_id: Some(2),
+                reason: RejectReason::Malformed,
+                ..
+            })
+        ));
+
+        let report = reader.report();
+        assert_eq!(report.lines_read, 5);
+        assert_eq!(report.lines_skipped, 2);
+        assert_eq!(
+            report.errors[0],
+            ParseError {
+                line_number: 3,
+                line: "ADD,2,A,LIMIT,abc,5".to_string(),
+                field: "price",
+                value: Some("abc".to_string()),
+            }
+        );
+        assert_eq!(
+            report.errors[1].to_string(),
+            "line 5: missing qty in 'ADD,3,B,MARKET'"
+        );
+    }
+
+    #[test]
+    fn test_strict_stops_at_first_bad_line() {
+        let mut reader = ReplayReader::new(REPLAY.as_bytes());
+        reader.set_parse_mode(ParseMode::Strict);
+
+        assert!(reader.next().unwrap().is_ok());
+        let error = reader.next().unwrap().unwrap_err();
+        let parse_error = error
+            .get_ref()
+            .unwrap()
+            .downcast_ref::<ParseError>()
+            .unwrap();
+        assert_eq!(parse_error.line_number, 3);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_trailing_fields_are_rejected() {
+        for line in [
+            "CANCEL,1,2",
+            "ADD,1,B,MARKET,5,ACCOUNT:1,X",
+            "ADD,1,B,LIMIT,100,5,BOGUS",
+        ] {
+            assert!(parse_event(line).is_err(), "{}", line);
+        }
+        assert!(parse_event("ADD,1,B,LIMIT,100,5,IOC,ACCOUNT:4").is_ok());
+    }
+
+    #[test]
+    fn test_symbol_flag() {
+        for line in [
+            "ADD,1,B,LIMIT,100,5,SYMBOL:3",
+            "ADD,1,B,MARKET,5,SYMBOL:3,ACCOUNT:1",
+            "ADD,1,B,STOP,100,5,ACCOUNT:1,SYMBOL:3",
+            "MODIFY,1,100,5,SYMBOL:3",
+            "CANCEL,1,SYMBOL:3",
+        ] {
+            assert_eq!(
+                parse_event(line).ok().and_then(|o| o.symbol()),
+                Some(3),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(
+            parse_event("CANCEL,1").ok().and_then(|o| o.symbol()),
+            Some(0)
+        );
+        assert!(parse_event("CANCEL,1,ACCOUNT:3").is_err());
+        assert!(parse_event("ADD,1,B,MARKET,5,SYMBOL:x").is_err());
+    }
+
+    #[test]
+    fn test_rejects_keep_the_line_symbol() {
+        let replay = "ADD,1,X,LIMIT,100,5,SYMBOL:3\n\
+                      ADD,2,A,LIMIT,abc,5,SYMBOL:3\n\
+                      ADD,3,A,MARKET,5,SYMBOL:x\n\
+                      ADD,4,X,LIMIT,100,5\n";
+        let orders = ReplayReader::new(replay.as_bytes()).parse_orders().unwrap();
+        assert!(matches!(
+            orders[0],
+            IncomingOrder::InboundInvalid(IncomingInvalidOrder {
+                reason: RejectReason::InvalidSide,
+                symbol: 3,
+                ..
+            })
+        ));
+        assert!(matches!(
+            orders[1],
+            IncomingOrder::InboundInvalid(IncomingInvalidOrder {
+                reason: RejectReason::Malformed,
+                symbol: 3,
+                ..
+            })
+        ));
+
+        // No readable symbol falls back to the default market
+        for order in &orders[2..] {
+            assert_eq!(order.symbol(), Some(DEFAULT_SYMBOL));
         }
     }
 }
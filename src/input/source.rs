@@ -0,0 +1,68 @@
+//! Where replay bytes come from: a file, stdin or any other reader
+//!
+//! Gzip and zstd compressed input is detected by its magic bytes and decompressed on the fly.
+
+use flate2::bufread::MultiGzDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Plain replay bytes, whatever the source and compression
+pub type InputReader = Box<dyn BufRead + Send>;
+
+/// Open `path` for reading, `-` is stdin
+pub fn open_input(path: &str) -> io::Result<InputReader> {
+    if path == "-" {
+        decompress(BufReader::new(io::stdin()))
+    } else {
+        decompress(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Wrap `reader` in a decoder if it starts with a gzip or zstd magic, otherwise pass it through
+pub fn decompress<R: BufRead + Send + 'static>(mut reader: R) -> io::Result<InputReader> {
+    let head = reader.fill_buf()?;
+    if head.starts_with(&GZIP_MAGIC) {
+        // Multi-member, so concatenated captures read as one stream
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else if head.starts_with(&ZSTD_MAGIC) {
+        Ok(Box::new(BufReader::new(
+            zstd::stream::read::Decoder::with_buffer(reader)?,
+        )))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::{Cursor, Read, Write};
+
+    const REPLAY: &[u8] = b"ADD,1,B,LIMIT,100,5\nCANCEL,1\n";
+
+    fn read_all(bytes: Vec<u8>) -> Vec<u8> {
+        let mut out = vec![];
+        decompress(Cursor::new(bytes))
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_plain_gzip_and_zstd_read_the_same() {
+        assert_eq!(read_all(REPLAY.to_vec()), REPLAY);
+
+        let mut gzip = GzEncoder::new(vec![], Compression::default());
+        gzip.write_all(REPLAY).unwrap();
+        assert_eq!(read_all(gzip.finish().unwrap()), REPLAY);
+
+        let zstd = zstd::encode_all(REPLAY, 0).unwrap();
+        assert_eq!(read_all(zstd), REPLAY);
+    }
+}
@@ -3,3 +3,4 @@ pub mod engine;
 pub mod input;
 pub mod logger;
 pub mod orderbook;
+pub mod storage;
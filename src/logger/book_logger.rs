@@ -1,4 +1,5 @@
-use crate::data::book_event::BookEvent;
+use crate::data::book_event::{BookEvent, EventEnvelope};
+use crate::logger::snapshot_writer::render_text;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 
@@ -14,8 +15,14 @@ impl BookLogger {
         })
     }
 
-    pub fn log(&mut self, event: &BookEvent) -> std::io::Result<()> {
-        let line = match event {
+    pub fn log(&mut self, entry: &EventEnvelope) -> std::io::Result<()> {
+        write!(
+            self.writer,
+            "seq({}),input({}),idx({}),symbol({}),",
+            entry.seq, entry.input_seq, entry.batch_index, entry.symbol
+        )?;
+
+        let line = match &entry.event {
             BookEvent::Match(event) => {
                 format!(
                     "MATCH,maker({}),taker({}),price({}),qty({}),ts({})\n",
@@ -28,14 +35,95 @@ impl BookLogger {
                     event.order_id, event.qty, event.ts
                 )
             }
+            BookEvent::Insert(event) if event.hidden_qty > 0 => {
+                format!(
+                    "INSERT,id({}),price({}),qty({}),hidden({}),side({}),ts({})\n",
+                    event.order_id, event.price, event.qty, event.hidden_qty, event.side, event.ts
+                )
+            }
             BookEvent::Insert(event) => {
                 format!(
                     "INSERT,id({}),price({}),qty({}),side({}),ts({})\n",
                     event.order_id, event.price, event.qty, event.side, event.ts
                 )
             }
-            BookEvent::BookSnapshot(data) => {
-                format!("--- Final book state ---\n{}\n", data)
+            BookEvent::Amend(event) => {
+                format!(
+                    "AMEND,id({}),price({}),qty({}),old_price({}),old_qty({}),ts({})\n",
+                    event.order_id,
+                    event.price,
+                    event.qty,
+                    event.old_price,
+                    event.old_qty,
+                    event.ts
+                )
+            }
+            BookEvent::Replenish(event) => {
+                format!(
+                    "REPLENISH,id({}),price({}),qty({}),hidden({}),ts({})\n",
+                    event.order_id, event.price, event.qty, event.hidden_qty, event.ts
+                )
+            }
+            BookEvent::Reject(event) => match event.order_id {
+                Some(order_id) => format!(
+                    "REJECT,id({}),reason({}),ts({})\n",
+                    order_id, event.reason, event.ts
+                ),
+                None => format!("REJECT,reason({}),ts({})\n", event.reason, event.ts),
+            },
+            BookEvent::Expire(event) => {
+                format!(
+                    "EXPIRE,id({}),qty({}),ts({})\n",
+                    event.order_id, event.qty, event.ts
+                )
+            }
+            BookEvent::Kill(event) => {
+                format!(
+                    "KILL,id({}),qty({}),tif({}),ts({})\n",
+                    event.order_id, event.qty, event.time_in_force, event.ts
+                )
+            }
+            BookEvent::BandCancel(event) => {
+                format!(
+                    "BAND_CANCEL,id({}),qty({}),band({}),ts({})\n",
+                    event.order_id, event.qty, event.band_price, event.ts
+                )
+            }
+            BookEvent::StopInsert(event) => match event.limit_price {
+                Some(limit_price) => format!(
+                    "STOP_INSERT,id({}),trigger({}),price({}),qty({}),side({}),ts({})\n",
+                    event.order_id,
+                    event.trigger_price,
+                    limit_price,
+                    event.qty,
+                    event.side,
+                    event.ts
+                ),
+                None => format!(
+                    "STOP_INSERT,id({}),trigger({}),qty({}),side({}),ts({})\n",
+                    event.order_id, event.trigger_price, event.qty, event.side, event.ts
+                ),
+            },
+            BookEvent::Trigger(event) => {
+                format!(
+                    "TRIGGER,id({}),trigger({}),last({}),ts({})\n",
+                    event.order_id, event.trigger_price, event.last_price, event.ts
+                )
+            }
+            BookEvent::SelfTrade(event) => {
+                format!(
+                    "SELF_TRADE,maker({}),taker({}),account({}),mode({}),maker_qty({}),taker_qty({}),ts({})\n",
+                    event.maker,
+                    event.taker,
+                    event.account_id,
+                    event.mode,
+                    event.maker_qty,
+                    event.taker_qty,
+                    event.ts
+                )
+            }
+            BookEvent::BookSnapshot(snapshot) => {
+                format!("--- Final book state ---\n{}\n", render_text(snapshot))
             }
         };
 
@@ -1 +1,2 @@
 pub mod book_logger;
+pub mod snapshot_writer;
@@ -0,0 +1,251 @@
+use crate::data::book_snapshot::{BookSnapshot, LevelSnapshot};
+use std::fmt::Write as _;
+use std::str::FromStr;
+
+/// Magic bytes opening a binary snapshot
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"BKSN";
+/// Version of the binary snapshot layout
+pub const SNAPSHOT_FORMAT_VERSION: u16 = 2;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    #[default]
+    Text,
+    Json,
+    Binary,
+}
+
+impl FromStr for SnapshotFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(SnapshotFormat::Text),
+            "json" => Ok(SnapshotFormat::Json),
+            "binary" => Ok(SnapshotFormat::Binary),
+            other => Err(format!("Unknown snapshot format: {}", other)),
+        }
+    }
+}
+
+pub fn render(snapshot: &BookSnapshot, format: SnapshotFormat) -> Vec<u8> {
+    match format {
+        SnapshotFormat::Text => render_text(snapshot).into_bytes(),
+        SnapshotFormat::Json => render_json(snapshot).into_bytes(),
+        SnapshotFormat::Binary => render_binary(snapshot),
+    }
+}
+
+/// Human readable form, as written to the output log
+pub fn render_text(snapshot: &BookSnapshot) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "---- SYMBOL {} ----", snapshot.symbol);
+    out.push_str("---- BIDS ----\n");
+    text_levels(&mut out, &snapshot.bids);
+    out.push_str("\n---- ASKS ----\n");
+    text_levels(&mut out, &snapshot.asks);
+
+    let _ = write!(
+        out,
+        "\nOrderBook checksum is: {} (v{})\nTop {} levels CRC32 is: {}\n",
+        snapshot.checksum, snapshot.checksum_version, snapshot.crc_depth, snapshot.top_levels_crc
+    );
+
+    out
+}
+
+fn text_levels(out: &mut String, levels: &[LevelSnapshot]) {
+    for level in levels {
+        let _ = write!(out, "Price: {} | Qty: {}", level.price, level.qty);
+        if level.hidden_qty > 0 {
+            let _ = write!(out, " | Hidden: {}", level.hidden_qty);
+        }
+        let _ = writeln!(out, " | Orders: {}", level.order_count);
+
+        for order in level.orders.iter().flatten() {
+            let _ = write!(out, "    id({}),qty({})", order.order_id, order.qty);
+            if order.hidden_qty > 0 {
+                let _ = write!(out, ",hidden({})", order.hidden_qty);
+            }
+            let _ = writeln!(out, ",account({}),ts({})", order.account_id, order.ts);
+        }
+    }
+}
+
+/// Single JSON object, one line
+///
+/// The checksum is written as a string, it does not fit in a JSON (f64) number.
+/// Levels only carry an `orders` array when orders were included in the snapshot.
+pub fn render_json(snapshot: &BookSnapshot) -> String {
+    let mut out = String::new();
+
+    let _ = write!(
+        out,
+        "{{\"symbol\":{},\"checksum\":\"{}\",\"checksum_version\":{},\"crc_depth\":{},\"top_levels_crc\":{},\"bids\":",
+        snapshot.symbol,
+        snapshot.checksum,
+        snapshot.checksum_version,
+        snapshot.crc_depth,
+        snapshot.top_levels_crc
+    );
+    json_levels(&mut out, &snapshot.bids);
+    out.push_str(",\"asks\":");
+    json_levels(&mut out, &snapshot.asks);
+    out.push_str("}\n");
+
+    out
+}
+
+fn json_levels(out: &mut String, levels: &[LevelSnapshot]) {
+    out.push('[');
+    for (i, level) in levels.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(
+            out,
+            "{{\"price\":{},\"qty\":{},\"hidden_qty\":{},\"order_count\":{}",
+            level.price, level.qty, level.hidden_qty, level.order_count
+        );
+
+        if let Some(orders) = &level.orders {
+            out.push_str(",\"orders\":[");
+            for (j, order) in orders.iter().enumerate() {
+                if j > 0 {
+                    out.push(',');
+                }
+                let _ = write!(
+                    out,
+                    "{{\"order_id\":{},\"qty\":{},\"hidden_qty\":{},\"account_id\":{},\"ts\":{}}}",
+                    order.order_id, order.qty, order.hidden_qty, order.account_id, order.ts
+                );
+            }
+            out.push(']');
+        }
+        out.push('}');
+    }
+    out.push(']');
+}
+
+/// Compact little-endian encoding
+///
+/// Header: magic `BKSN`, format version (u16), symbol (u32), checksum version (u32), checksum (u64),
+/// CRC depth (u32), top levels CRC (u32). Then bids and asks, each as a level count (u32)
+/// followed by per level: price (u64), qty (u64), hidden qty (u64), order count (u64) and
+/// the number of included orders (u32, 0 when orders were not requested), then per order:
+/// order id (u64), qty (u32), hidden qty (u32), account id (u64), ts (i64).
+pub fn render_binary(snapshot: &BookSnapshot) -> Vec<u8> {
+    let mut out = vec![];
+
+    out.extend_from_slice(&SNAPSHOT_MAGIC);
+    out.extend_from_slice(&SNAPSHOT_FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&snapshot.symbol.to_le_bytes());
+    out.extend_from_slice(&snapshot.checksum_version.to_le_bytes());
+    out.extend_from_slice(&snapshot.checksum.to_le_bytes());
+    out.extend_from_slice(&snapshot.crc_depth.to_le_bytes());
+    out.extend_from_slice(&snapshot.top_levels_crc.to_le_bytes());
+
+    binary_levels(&mut out, &snapshot.bids);
+    binary_levels(&mut out, &snapshot.asks);
+
+    out
+}
+
+fn binary_levels(out: &mut Vec<u8>, levels: &[LevelSnapshot]) {
+    out.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+    for level in levels {
+        out.extend_from_slice(&level.price.to_le_bytes());
+        out.extend_from_slice(&level.qty.to_le_bytes());
+        out.extend_from_slice(&level.hidden_qty.to_le_bytes());
+        out.extend_from_slice(&level.order_count.to_le_bytes());
+
+        let orders = level.orders.as_deref().unwrap_or_default();
+        out.extend_from_slice(&(orders.len() as u32).to_le_bytes());
+        for order in orders {
+            out.extend_from_slice(&order.order_id.to_le_bytes());
+            out.extend_from_slice(&order.qty.to_le_bytes());
+            out.extend_from_slice(&order.hidden_qty.to_le_bytes());
+            out.extend_from_slice(&order.account_id.to_le_bytes());
+            out.extend_from_slice(&order.ts.to_le_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::book_snapshot::OrderSnapshot;
+
+    fn snapshot(include_orders: bool) -> BookSnapshot {
+        BookSnapshot {
+            symbol: 3,
+            bids: vec![LevelSnapshot {
+                price: 100,
+                qty: 8,
+                hidden_qty: 0,
+                order_count: 2,
+                orders: include_orders.then(|| {
+                    vec![
+                        OrderSnapshot {
+                            order_id: 1,
+                            qty: 5,
+                            hidden_qty: 0,
+                            account_id: 0,
+                            ts: 1,
+                        },
+                        OrderSnapshot {
+                            order_id: 2,
+                            qty: 3,
+                            hidden_qty: 0,
+                            account_id: 7,
+                            ts: 2,
+                        },
+                    ]
+                }),
+            }],
+            asks: vec![],
+            checksum: u64::MAX,
+            checksum_version: 1,
+            crc_depth: 25,
+            top_levels_crc: 42,
+        }
+    }
+
+    #[test]
+    fn test_render_text() {
+        assert_eq!(
+            render_text(&snapshot(false)),
+            "---- SYMBOL 3 ----\n---- BIDS ----\nPrice: 100 | Qty: 8 | Orders: 2\n\n---- ASKS ----\n\n\
+             OrderBook checksum is: 18446744073709551615 (v1)\nTop 25 levels CRC32 is: 42\n"
+        );
+        assert!(render_text(&snapshot(true)).contains("    id(2),qty(3),account(7),ts(2)\n"));
+    }
+
+    #[test]
+    fn test_render_json() {
+        assert_eq!(
+            render_json(&snapshot(false)),
+            "{\"symbol\":3,\"checksum\":\"18446744073709551615\",\"checksum_version\":1,\"crc_depth\":25,\
+             \"top_levels_crc\":42,\"bids\":[{\"price\":100,\"qty\":8,\"hidden_qty\":0,\
+             \"order_count\":2}],\"asks\":[]}\n"
+        );
+        assert!(render_json(&snapshot(true)).contains(
+            "\"orders\":[{\"order_id\":1,\"qty\":5,\"hidden_qty\":0,\"account_id\":0,\"ts\":1},"
+        ));
+    }
+
+    #[test]
+    fn test_render_binary_layout() {
+        let header = 4 + 2 + 4 + 4 + 8 + 4 + 4;
+        let level = 8 * 4 + 4;
+        let order = 8 + 4 + 4 + 8 + 8;
+
+        let bytes = render_binary(&snapshot(false));
+        assert_eq!(&bytes[..4], b"BKSN");
+        assert_eq!(bytes.len(), header + 4 + level + 4);
+
+        let bytes = render_binary(&snapshot(true));
+        assert_eq!(bytes.len(), header + 4 + level + 2 * order + 4);
+    }
+}
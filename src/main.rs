@@ -1,14 +1,27 @@
 use clap::Parser;
-use matching_engine::data::book_event::BookEvent;
-use matching_engine::data::order_types::IncomingOrder;
-use matching_engine::engine::matching_engine::Engine;
+use matching_engine::data::book_event::{BookEvent, EventEnvelope};
+use matching_engine::data::order_types::{IncomingOrder, StpMode};
+use matching_engine::engine::clock::clock_from_name;
+use matching_engine::engine::instrument::load_specs;
+use matching_engine::engine::matching_engine::{DuplicateIdPolicy, Engine};
+use matching_engine::engine::price_band::{BandReference, PriceBand};
+use matching_engine::engine::symbols::SymbolRegistry;
+use matching_engine::input::binary_replay::{BinaryReplayReader, ReplayFormat};
 use matching_engine::input::generator::Generator;
-use matching_engine::input::replay_reader::ReplayReader;
+use matching_engine::input::replay_reader::{ParseReport, ReplayReader};
+use matching_engine::input::source::InputReader;
 use matching_engine::logger::book_logger::BookLogger;
-use rtrb::RingBuffer;
+use matching_engine::logger::snapshot_writer::{SnapshotFormat, render};
+use matching_engine::storage::journal::{Journal, recover};
+use matching_engine::storage::snapshot_file::{load_state, save_state};
+use rtrb::{Consumer, Producer, PushError, RingBuffer};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -28,83 +41,546 @@ struct Args {
     #[arg(long, default_value = "10000")]
     num_of_events: usize,
 
-    /// Input file for replay mode
+    /// Input file for replay mode, `-` for stdin. Gzip and zstd files are decompressed
     #[arg(long)]
     input: Option<String>,
 
+    /// Replay file format, for both the generated file and --input: csv or binary
+    #[arg(long, default_value = "csv")]
+    format: String,
+
+    /// Bad CSV replay lines: strict stops at the first one, lenient rejects them and carries on
+    #[arg(long, default_value = "lenient")]
+    parse_mode: String,
+
+    /// Write a report of the CSV replay lines that could not be parsed to this file
+    #[arg(long)]
+    parse_report: Option<String>,
+
+    /// TOML file listing the markets to run, one book each. Without it a single market, symbol 0
+    #[arg(long)]
+    symbols: Option<String>,
+
+    /// TOML or JSON (.json) file of instrument specs: tick and lot size, min/max qty and price
+    #[arg(long)]
+    instruments: Option<String>,
+
     /// Output file
     #[arg(long, default_value = "output.log")]
     output: String,
+
+    /// Self-trade prevention: cancel-newest, cancel-oldest, cancel-both or decrement-and-cancel
+    #[arg(long)]
+    stp_mode: Option<String>,
+
+    /// Half-width of the price band in basis points of the reference price, no band without it
+    #[arg(long)]
+    price_band_bps: Option<u32>,
+
+    /// Price the band is centred on: last-trade or mid
+    #[arg(long, default_value = "last-trade")]
+    band_reference: String,
+
+    /// Reject any order id already seen in this session, not only live ones
+    #[arg(long)]
+    reject_reused_ids: bool,
+
+    /// Event timestamps: logical (input sequence), replay (TIME lines) or wall
+    #[arg(long, default_value = "logical")]
+    clock: String,
+
+    /// Also write the final book snapshots to this file, one per market
+    #[arg(long)]
+    snapshot_output: Option<String>,
+
+    /// Format of --snapshot-output: text, json or binary
+    #[arg(long, default_value = "text")]
+    snapshot_format: String,
+
+    /// List every resting order in FIFO order in the final snapshot
+    #[arg(long)]
+    snapshot_orders: bool,
+
+    /// Restore the engine from a state file before processing any input
+    #[arg(long)]
+    restore_state: Option<String>,
+
+    /// Save the full engine state to this file after processing
+    #[arg(long)]
+    save_state: Option<String>,
+
+    /// Journal every input to this directory before matching, with periodic snapshots
+    #[arg(long)]
+    journal_dir: Option<String>,
+
+    /// Inputs between journal snapshots, 0 only snapshots at the end of the run
+    #[arg(long, default_value = "10000")]
+    snapshot_every: u64,
+
+    /// Inputs per journal disk sync. 1 syncs every input before matching it, larger values
+    /// batch syncs (group commit) and may lose that many inputs minus one on power loss
+    #[arg(long, default_value = "1")]
+    journal_sync_every: u64,
+
+    /// Rebuild the engine from --journal-dir (latest snapshot plus journal tail) first
+    #[arg(long)]
+    recover: bool,
 }
 
 const DEFAULT_SIZE: usize = 1 << 16;
 
+/// Where inputs come from, read or generated on demand
+enum InputSource {
+    Generated(Box<Generator>, usize),
+    Csv(ReplayReader<InputReader>),
+    Binary(BinaryReplayReader<InputReader>),
+    Empty,
+}
+
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
-    // Input generation of orders
-    let input_events: Vec<IncomingOrder> = match args.mode.as_str() {
+    let format: ReplayFormat = args.format.parse().map_err(anyhow::Error::msg)?;
+    let registry = match &args.symbols {
+        Some(path) => SymbolRegistry::load(path)?,
+        None => SymbolRegistry::default(),
+    };
+    let symbols: Vec<_> = registry.ids().collect();
+
+    // Inputs are produced lazily and streamed to the engine, never held in memory
+    let mut input_source = match args.mode.as_str() {
         "gen" => {
             println!("Generating random input...");
-            let mut generator = Generator::new(args.seed, args.mid_price, &args.replay_output)?;
-            generator.generate(args.num_of_events) // generate N events
+            let mut generator =
+                Generator::new(args.seed, args.mid_price, &args.replay_output, format)?;
+            generator.set_symbols(symbols.clone());
+            InputSource::Generated(Box::new(generator), args.num_of_events) // generate N events
         }
         "replay" => {
-            println!("Loading replay file...");
-            let input = args.input.expect("Replay mode requires --input <file>");
-            let mut reader = ReplayReader::from_file(input.as_str())?;
-            reader.parse_orders()
+            println!("Streaming replay file...");
+            match args.input.as_deref() {
+                Some(input) => match format {
+                    ReplayFormat::Csv => {
+                        let mut reader = ReplayReader::from_file(input)?;
+                        reader.set_parse_mode(args.parse_mode.parse().map_err(anyhow::Error::msg)?);
+                        InputSource::Csv(reader)
+                    }
+                    ReplayFormat::Binary => {
+                        InputSource::Binary(BinaryReplayReader::from_file(input)?)
+                    }
+                },
+                // Recovery alone has no new input
+                None if args.recover => InputSource::Empty,
+                None => panic!("Replay mode requires --input <file>"),
+            }
         }
         _ => panic!("Invalid mode. Use --mode gen or --mode replay"),
     };
 
-    println!("Loaded {} input events", input_events.len());
-
     // Init ring buffer and syncing atmoic bool
-    let mut engine = Engine::new(1 << 16);
-    let (mut producer, mut consumer) = RingBuffer::<BookEvent>::new(DEFAULT_SIZE);
+    let mut tail = vec![];
+    let mut engine = match (&args.restore_state, &args.journal_dir) {
+        (_, Some(dir)) if args.recover => {
+            let recovery = recover(Path::new(dir), 1 << 16, &symbols)?;
+            println!("Recovered {} journaled input events", recovery.tail.len());
+            tail = recovery.tail;
+            recovery.engine
+        }
+        (Some(path), _) => load_state(path, 1 << 16)?,
+        _ if args.recover => anyhow::bail!("--recover requires --journal-dir"),
+        _ => Engine::with_symbols(1 << 16, symbols.iter().copied()),
+    };
+    // Restored state brings its own markets, they have to be the configured ones
+    if !engine
+        .markets()
+        .map(|(symbol, _)| symbol)
+        .eq(symbols.iter().copied())
+    {
+        anyhow::bail!("Restored markets do not match the configured symbols");
+    }
+    let mut journal = args
+        .journal_dir
+        .as_deref()
+        .map(|dir| {
+            let input_seq = engine.counters().input_seq + tail.len() as u64;
+            let mut journal = Journal::open(Path::new(dir), args.snapshot_every, input_seq)?;
+            journal.set_sync_every(args.journal_sync_every);
+            Ok::<_, io::Error>(journal)
+        })
+        .transpose()?;
+    // Restored state gets a base snapshot, recovery could not rebuild it from the journal
+    if let Some(journal) = &mut journal
+        && !args.recover
+        && engine.counters().input_seq > 0
+    {
+        journal.snapshot(&engine)?;
+    }
+    let stp_mode: Option<StpMode> = args
+        .stp_mode
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+    for spec in args
+        .instruments
+        .as_deref()
+        .map(load_specs)
+        .transpose()?
+        .unwrap_or_default()
+    {
+        if !engine.set_instrument_spec(spec) {
+            anyhow::bail!("Instrument spec for unknown symbol {}", spec.symbol);
+        }
+    }
+    engine.set_stp_mode(stp_mode);
+    let band_reference: BandReference = args.band_reference.parse().map_err(anyhow::Error::msg)?;
+    engine.set_price_band(args.price_band_bps.map(|width_bps| PriceBand {
+        reference: band_reference,
+        width_bps,
+    }));
+    engine.set_clock(clock_from_name(&args.clock).map_err(anyhow::Error::msg)?);
+    let snapshot_format: SnapshotFormat =
+        args.snapshot_format.parse().map_err(anyhow::Error::msg)?;
+    let snapshot_orders = args.snapshot_orders;
+    if args.reject_reused_ids {
+        engine.set_duplicate_id_policy(DuplicateIdPolicy::Session);
+    }
+    let (mut input_producer, mut input_consumer) = RingBuffer::<IncomingOrder>::new(DEFAULT_SIZE);
+    let input_status = Arc::new(InputStatus::default());
+    let input_status_producer = input_status.clone();
+    let (mut producer, mut consumer) = RingBuffer::<EventEnvelope>::new(DEFAULT_SIZE);
     let done = Arc::new(AtomicBool::new(false));
     let done_producer = done.clone();
     let output_path = args.output;
+    let run = EngineRun {
+        engine,
+        tail,
+        journal,
+        snapshot_orders,
+        save_path: args.save_state,
+    };
+
+    // Spawn input(reader) thread, parsing or generating ahead of the engine
+    let input_handle = thread::spawn(move || -> anyhow::Result<(usize, Option<ParseReport>)> {
+        let producer = &mut input_producer;
+        let status = &input_status_producer;
+        let result = match &mut input_source {
+            InputSource::Generated(generator, num_events) => feed_inputs(
+                (0..*num_events).map(|_| Ok(generator.next_event())),
+                producer,
+                status,
+            ),
+            InputSource::Csv(reader) => feed_inputs(reader, producer, status),
+            InputSource::Binary(reader) => feed_inputs(reader, producer, status),
+            InputSource::Empty => feed_inputs(std::iter::empty(), producer, status),
+        };
+
+        let report = match &input_source {
+            InputSource::Csv(reader) => Some(reader.report().clone()),
+            _ => None,
+        };
+        Ok((result?, report))
+    });
 
     // Spawn engine(producer) thread
     let engine_handle = thread::spawn(move || -> anyhow::Result<()> {
+        let result = run.run(&mut input_consumer, &input_status, &mut producer);
+        // Also on error, so the logger does not wait for events that never come
+        done_producer.store(true, Ordering::Release);
+        result
+    });
+
+    let mut logger = BookLogger::new(&output_path)?;
+    let mut snapshot_file = args
+        .snapshot_output
+        .as_deref()
+        .map(File::create)
+        .transpose()?;
+    while let Some(event) = pop_blocking(&mut consumer, &done) {
+        if let (BookEvent::BookSnapshot(snapshot), Some(file)) = (&event.event, &mut snapshot_file)
+        {
+            file.write_all(&render(snapshot, snapshot_format))?;
+        }
+        logger.log(&event)?;
+    }
+
+    logger.flush()?;
+    engine_handle.join().unwrap()?;
+    let (input_count, report) = input_handle.join().unwrap()?;
+
+    println!("Processed {} input events", input_count);
+    if let Some(report) = report {
+        if report.lines_skipped > 0 {
+            println!("Skipped {} malformed replay lines", report.lines_skipped);
+        }
+        if let Some(path) = &args.parse_report {
+            std::fs::write(path, report.to_string())?;
+        }
+    }
+    println!("Done.");
+
+    Ok(())
+}
+
+/// Progress of the input thread, shared with the engine thread
+#[derive(Default)]
+struct InputStatus {
+    done: AtomicBool,   // No more inputs will be pushed
+    failed: AtomicBool, // The stream ended on an error, set before `done`
+}
+
+/// Push every input onto the ring in order, returning how many there were
+/// `done` is set at the end, also on error so the engine does not wait for input that never comes
+fn feed_inputs(
+    source: impl Iterator<Item = io::Result<IncomingOrder>>,
+    producer: &mut Producer<IncomingOrder>,
+    status: &InputStatus,
+) -> anyhow::Result<usize> {
+    let result = (|| -> anyhow::Result<_> {
+        let mut count = 0;
+        for order in source {
+            push_blocking(producer, order?)?;
+            count += 1;
+        }
+        Ok(count)
+    })();
+    if result.is_err() {
+        status.failed.store(true, Ordering::Release);
+    }
+    status.done.store(true, Ordering::Release);
+    result
+}
+
+/// Engine side of a run, owned by the engine thread
+struct EngineRun {
+    engine: Engine,
+    tail: Vec<IncomingOrder>, // Recovered journal records not covered by the snapshot
+    journal: Option<Journal>,
+    snapshot_orders: bool,
+    save_path: Option<String>,
+}
+
+impl EngineRun {
+    /// Match every input, then write the final journal snapshot, book snapshots and state file
+    ///
+    /// Nothing final is written if the input stream failed (e.g. a strict-mode parse error),
+    /// so a half-read input never looks like a complete run. The input thread reports the error.
+    fn run(
+        mut self,
+        inputs: &mut Consumer<IncomingOrder>,
+        status: &InputStatus,
+        producer: &mut Producer<EventEnvelope>,
+    ) -> anyhow::Result<()> {
+        let engine = &mut self.engine;
+
+        // Recovered inputs are already in the journal, replay them before new input
+        for order in self.tail {
+            for event in engine.submit(order) {
+                push_blocking(producer, event)?;
+            }
+            if let Some(journal) = &mut self.journal {
+                journal.checkpoint(engine)?;
+            }
+        }
+
         // Perform main engine matching task
-        for order in input_events {
-            let events = engine.match_order(order);
+        while let Some(order) = pop_blocking(inputs, &status.done) {
+            if let Some(journal) = &mut self.journal {
+                journal.append(engine.counters().input_seq + 1, &order)?;
+            }
+
+            let events = engine.submit(order);
             for event in events {
-                producer.push(event)?;
+                push_blocking(producer, event)?;
+            }
+
+            if let Some(journal) = &mut self.journal {
+                journal.checkpoint(engine)?;
             }
         }
 
-        let book_state = engine.get_book().print_book();
-        // Should only be one element
-        for event in book_state {
-            producer.push(event)?;
+        if status.failed.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        if let Some(journal) = &mut self.journal {
+            journal.snapshot(engine)?;
+        }
+
+        // One snapshot per market
+        for event in engine.snapshot(self.snapshot_orders) {
+            push_blocking(producer, event)?;
+        }
+
+        if let Some(path) = &self.save_path {
+            save_state(engine, path)?;
         }
-        done_producer.store(true, Ordering::Release);
         Ok(())
-    });
+    }
+}
 
-    let mut logger = BookLogger::new(&output_path)?;
+const SPINS: u32 = 64; // Busy waits before yielding the core
+const YIELDS: u32 = 64; // Yields before sleeping between retries
+const IDLE_SLEEP: Duration = Duration::from_micros(100);
+
+/// Wait strategy for an empty or full ring
+///
+/// Spins first to keep hand-off latency low under load, then yields, then sleeps so an
+/// idle thread (e.g. waiting on a slow `--input -` pipe) does not hold a core at 100%.
+#[derive(Default)]
+struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    fn snooze(&mut self) {
+        if self.step < SPINS {
+            std::hint::spin_loop();
+        } else if self.step < SPINS + YIELDS {
+            thread::yield_now();
+        } else {
+            thread::sleep(IDLE_SLEEP);
+        }
+        self.step = self.step.saturating_add(1);
+    }
+}
+
+/// Push onto a ring, waiting while it is full so nothing is dropped
+fn push_blocking<T>(producer: &mut Producer<T>, mut value: T) -> anyhow::Result<()> {
+    let mut backoff = Backoff::default();
     loop {
-        match consumer.pop() {
-            Ok(event) => {
-                logger.log(&event)?;
-            }
-            Err(_) => {
-                if done.load(Ordering::Acquire) {
-                    break;
+        match producer.push(value) {
+            Ok(()) => return Ok(()),
+            Err(PushError::Full(rejected)) => {
+                if producer.is_abandoned() {
+                    anyhow::bail!("Ring consumer stopped");
                 }
-                // Otherwise just continue trying
+                value = rejected;
+                backoff.snooze();
             }
         }
     }
+}
 
-    logger.flush()?;
-    engine_handle.join().unwrap()?;
+/// Pop from a ring, waiting for the producer. None once `done` is set and the ring is drained
+fn pop_blocking<T>(consumer: &mut Consumer<T>, done: &AtomicBool) -> Option<T> {
+    let mut backoff = Backoff::default();
+    loop {
+        if let Ok(value) = consumer.pop() {
+            return Some(value);
+        }
+        // Items pushed right before `done` was set may still be in the ring
+        if done.load(Ordering::Acquire) && consumer.is_empty() {
+            return None;
+        }
+        backoff.snooze();
+    }
+}
 
-    println!("Done.");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matching_engine::input::binary_replay::ReplayFormat;
+    use matching_engine::input::replay_reader::ParseMode;
 
-    Ok(())
+    fn generator(path: &Path) -> Generator {
+        Generator::new(7, 1_000, &path.to_string_lossy(), ReplayFormat::Csv).unwrap()
+    }
+
+    #[test]
+    fn test_streamed_run_matches_generate() {
+        let dir = std::env::temp_dir().join(format!("ring-stream-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let expected = generator(&dir.join("batch.csv")).generate(2_000);
+
+        // A small ring keeps both threads waiting on each other
+        let (mut producer, mut consumer) = RingBuffer::<IncomingOrder>::new(8);
+        let status = Arc::new(InputStatus::default());
+        let status_producer = status.clone();
+        let path = dir.join("streamed.csv");
+        let handle = thread::spawn(move || {
+            let mut generator = generator(&path);
+            feed_inputs(
+                (0..2_000).map(|_| Ok(generator.next_event())),
+                &mut producer,
+                &status_producer,
+            )
+        });
+
+        let mut streamed = vec![];
+        while let Some(order) = pop_blocking(&mut consumer, &status.done) {
+            streamed.push(order);
+        }
+        assert_eq!(handle.join().unwrap().unwrap(), 2_000);
+        assert_eq!(format!("{:?}", streamed), format!("{:?}", expected));
+        assert_eq!(
+            std::fs::read(dir.join("streamed.csv")).unwrap(),
+            std::fs::read(dir.join("batch.csv")).unwrap()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_input_error_ends_the_stream() {
+        let (mut producer, mut consumer) = RingBuffer::<IncomingOrder>::new(8);
+        let status = Arc::new(InputStatus::default());
+        let status_producer = status.clone();
+        let handle = thread::spawn(move || {
+            let source = ReplayReader::new("CANCEL,1\nCANCEL,2\n".as_bytes())
+                .chain([Err(io::Error::other("pipe closed"))])
+                .chain(ReplayReader::new("CANCEL,3\n".as_bytes()));
+            feed_inputs(source, &mut producer, &status_producer)
+        });
+
+        // Inputs before the error still arrive, then the consumer is released
+        let mut received = 0;
+        while pop_blocking(&mut consumer, &status.done).is_some() {
+            received += 1;
+        }
+        assert_eq!(received, 2);
+        assert!(handle.join().unwrap().is_err());
+        assert!(status.failed.load(Ordering::Acquire));
+    }
+
+    /// Stream a CSV replay through an engine run that saves its state to `path`
+    fn run_replay(replay: &'static str, mode: ParseMode, path: &Path) -> anyhow::Result<usize> {
+        let (mut producer, mut consumer) = RingBuffer::<IncomingOrder>::new(8);
+        let status = Arc::new(InputStatus::default());
+        let status_producer = status.clone();
+        let handle = thread::spawn(move || {
+            let mut reader = ReplayReader::new(replay.as_bytes());
+            reader.set_parse_mode(mode);
+            feed_inputs(reader, &mut producer, &status_producer)
+        });
+
+        let (mut events, _log) = RingBuffer::<EventEnvelope>::new(64);
+        let run = EngineRun {
+            engine: Engine::new(1024),
+            tail: vec![],
+            journal: None,
+            snapshot_orders: false,
+            save_path: Some(path.to_string_lossy().into_owned()),
+        };
+        run.run(&mut consumer, &status, &mut events).unwrap();
+        handle.join().unwrap()
+    }
+
+    #[test]
+    fn test_strict_parse_error_leaves_no_state_file() {
+        let dir = std::env::temp_dir().join(format!("strict-state-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let replay = "ADD,1,A,LIMIT,100,5\nADD,2,A,LIMIT,oops,5\n";
+
+        let path = dir.join("strict.bin");
+        assert!(run_replay(replay, ParseMode::Strict, &path).is_err());
+        assert!(!path.exists());
+
+        // The same input in lenient mode runs to the end and saves
+        let path = dir.join("lenient.bin");
+        assert_eq!(run_replay(replay, ParseMode::Lenient, &path).unwrap(), 2);
+        assert!(path.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,2 +1,3 @@
 pub mod order_book;
+pub mod stop_book;
 pub mod util;
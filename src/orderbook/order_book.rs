@@ -1,18 +1,25 @@
-use crate::data::book_event::{BookEvent, CancelEvent, InsertEvent};
-use crate::data::order_types::IncomingSide;
-use crate::data::orders::inbound_orders::{IncomingLimitOrder, IncomingMarketOrder};
-use crate::data::orders::resting_orders::{OrderId, RestingOrder};
+use crate::data::book_depth::BookDepth;
+use crate::data::book_event::{
+    AmendEvent, BookEvent, CancelEvent, ExpireEvent, InsertEvent, RejectReason,
+};
+use crate::data::book_snapshot::BookSnapshot;
+use crate::data::fill_quote::FillQuote;
+use crate::data::order_types::{DEFAULT_SYMBOL, IncomingSide, StpMode};
+use crate::data::orders::inbound_orders::{
+    IncomingIcebergOrder, IncomingLimitOrder, IncomingMarketOrder, IncomingModifyOrder,
+};
+use crate::data::orders::resting_orders::{AccountId, OrderId, RestingOrder};
 use crate::orderbook::util::book_side::BookSide;
+use crate::orderbook::util::checksum::{
+    CHECKSUM_VERSION, CRC_DEPTH, Fnv1a, crc32, top_levels_string,
+};
 use crate::orderbook::util::match_iter::MatchIter;
 use crate::orderbook::util::price_key::PriceKey;
-use crate::orderbook::util::side::{Asks, Bids};
+use crate::orderbook::util::side::{Asks, Bids, Side};
 
-use chrono::Utc;
 use rustc_hash::{FxBuildHasher, FxHashMap};
 use slab::Slab;
 use std::cmp::Reverse;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
 
 pub struct OrderBook {
     bids: BookSide<Bids>,
@@ -20,6 +27,16 @@ pub struct OrderBook {
 
     orders: Slab<RestingOrder>,
     order_map: FxHashMap<OrderId, usize>,
+
+    stp_mode: Option<StpMode>,
+    now: i64, // Engine time, set by the engine before each input
+}
+
+/// Where a resting order stands in its price level's FIFO queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuePosition {
+    pub index: u64,     // Orders ahead, 0 at the head of the queue
+    pub qty_ahead: u64, // Visible qty of those orders, their reserve refills behind us
 }
 
 impl Default for OrderBook {
@@ -29,6 +46,8 @@ impl Default for OrderBook {
             asks: BookSide::default(),
             orders: Slab::with_capacity(262144),
             order_map: FxHashMap::with_capacity_and_hasher(262144, FxBuildHasher),
+            stp_mode: None,
+            now: 0,
         }
     }
 }
@@ -40,6 +59,8 @@ impl OrderBook {
             asks: BookSide::default(),
             orders: Slab::with_capacity(capacity),
             order_map: FxHashMap::with_capacity_and_hasher(capacity, FxBuildHasher),
+            stp_mode: None,
+            now: 0,
         }
     }
 
@@ -61,18 +82,62 @@ impl OrderBook {
         remaining: u32,
     ) -> BookEvent {
         let mut order = order.into();
-        order.qty = remaining;
-        let idx = self.orders.insert(order);
-        self.order_map.insert(self.orders[idx].order_id, idx);
+        order.ts = self.now;
+        // Iceberg orders only show their display slice, the rest is held in reserve
+        order.qty = order
+            .display_qty
+            .map_or(remaining, |display| remaining.min(display));
+        order.hidden_qty = remaining - order.qty;
+        let idx = self.link_tail(order);
 
         let price = self.orders[idx].price;
-        let side;
-        let level = if IS_BID {
-            side = IncomingSide::Buy;
-            self.bids.level_mut(Reverse(PriceKey(price)))
+        let side = if IS_BID {
+            IncomingSide::Buy
         } else {
-            side = IncomingSide::Sell;
-            self.asks.level_mut(PriceKey(price))
+            IncomingSide::Sell
+        };
+
+        BookEvent::Insert(InsertEvent {
+            order_id: self.orders[idx].order_id,
+            price,
+            qty: self.orders[idx].qty,
+            side,
+            hidden_qty: self.orders[idx].hidden_qty,
+            ts: self.now,
+        })
+    }
+
+    /// Put a restored order back at the tail of its level, exactly as it was saved
+    /// Orders must be restored in the FIFO order `fifo_orders` returns them in
+    pub fn restore_order(&mut self, mut order: RestingOrder) {
+        order.prev = None;
+        order.next = None;
+        self.link_tail(order);
+    }
+
+    /// Every resting order, bids then asks, best level first and FIFO within a level
+    pub fn fifo_orders(&self) -> impl Iterator<Item = &RestingOrder> {
+        let bids = self.bids.levels.values().map(|level| level.head);
+        let asks = self.asks.levels.values().map(|level| level.head);
+
+        bids.chain(asks).flat_map(|head| {
+            std::iter::successors(head, |idx| self.orders[*idx].next).map(|idx| &self.orders[idx])
+        })
+    }
+
+    /// Store an order and append it to the tail of its price level
+    fn link_tail(&mut self, order: RestingOrder) -> usize {
+        let idx = self.orders.insert(order);
+        let replaced = self.order_map.insert(self.orders[idx].order_id, idx);
+        debug_assert!(
+            replaced.is_none(),
+            "Duplicate order ids must be rejected upstream"
+        );
+
+        let price = self.orders[idx].price;
+        let level = match self.orders[idx].side {
+            IncomingSide::Buy => self.bids.level_mut(Reverse(PriceKey(price))),
+            IncomingSide::Sell => self.asks.level_mut(PriceKey(price)),
         };
 
         // Update FIFO
@@ -85,30 +150,160 @@ impl OrderBook {
             level.head = Some(idx);
         }
         level.total_orders += 1;
+        level.total_qty += self.orders[idx].qty as u64;
+        level.hidden_qty += self.orders[idx].hidden_qty as u64;
 
-        BookEvent::Insert(InsertEvent {
-            order_id: self.orders[idx].order_id,
-            price,
-            qty: remaining,
-            side,
-            ts: Utc::now().timestamp_micros(),
-        })
+        idx
     }
 
     /// Cancel an existing order by OrderId
-    /// Will do nothing if order doesn't exist
+    /// Rejects with UnknownOrderId if order doesn't exist
     pub fn cancel_order(&mut self, order_id: OrderId) -> Vec<BookEvent> {
-        let idx = match self.order_map.remove(&order_id) {
-            Some(i) => i,
-            None => {
-                // TODO: Better logging here
-                println!("Order id not found during cancel");
-                return vec![];
-            }
+        let Some(order) = self.remove_order(order_id) else {
+            return vec![BookEvent::reject(
+                Some(order_id),
+                RejectReason::UnknownOrderId,
+                self.now,
+            )];
         };
 
+        vec![BookEvent::Cancel(CancelEvent {
+            order_id,
+            qty: order.total_qty(),
+            ts: self.now,
+        })]
+    }
+
+    /// Amend a resting order to a new price and total remaining quantity
+    ///
+    /// A reduction at the same price is applied in place and keeps queue position.
+    /// Anything else is a cancel-replace: the order goes through matching again at its
+    /// new price and any remainder rests at the tail of the level (Amend, Match..., Insert).
+    /// An amend to zero quantity cancels the order.
+    /// Post-only orders are rejected with PostOnlyWouldCross instead of crossing the spread.
+    pub fn amend_order(&mut self, amend: &IncomingModifyOrder) -> Vec<BookEvent> {
+        let now = self.now;
+        let Some(order) = self.get_order(amend.order_id) else {
+            return vec![BookEvent::reject(
+                Some(amend.order_id),
+                RejectReason::UnknownOrderId,
+                now,
+            )];
+        };
+
+        if amend.qty == 0 {
+            return self.cancel_order(amend.order_id);
+        }
+
+        // Post-only orders stay passive, an amend through the spread leaves the order as it was
+        if order.post_only && self.crosses(&order.side, amend.price) {
+            return vec![BookEvent::reject(
+                Some(amend.order_id),
+                RejectReason::PostOnlyWouldCross,
+                now,
+            )];
+        }
+
+        let order = self.get_order_mut(amend.order_id).expect("Looked up above");
+
+        let event = BookEvent::Amend(AmendEvent {
+            order_id: amend.order_id,
+            old_price: order.price,
+            old_qty: order.total_qty(),
+            price: amend.price,
+            qty: amend.qty,
+            ts: now,
+        });
+
+        // Same price and not bigger -> keep position in the linked list
+        if amend.price == order.price && amend.qty <= order.total_qty() {
+            let (old_qty, old_hidden) = (order.qty, order.hidden_qty);
+            order.qty = order.qty.min(amend.qty);
+            order.hidden_qty = amend.qty - order.qty;
+            let (qty, hidden_qty) = (order.qty, order.hidden_qty);
+
+            let level = match order.side {
+                IncomingSide::Buy => self.bids.level_mut(Reverse(PriceKey(amend.price))),
+                IncomingSide::Sell => self.asks.level_mut(PriceKey(amend.price)),
+            };
+            level.total_qty -= (old_qty - qty) as u64;
+            level.hidden_qty -= (old_hidden - hidden_qty) as u64;
+            return vec![event];
+        }
+
+        let mut events = vec![event];
+        let Some(mut order) = self.remove_order(amend.order_id) else {
+            return events;
+        };
+        order.price = amend.price;
+        order.prev = None;
+        order.next = None;
+        order.ts = self.now;
+
+        match order.side {
+            IncomingSide::Buy => {
+                let mut iter = self.match_asks(
+                    amend.order_id,
+                    order.account_id,
+                    amend.qty,
+                    Some(PriceKey(amend.price)),
+                );
+                events.extend(iter.by_ref());
+                let remaining = iter.remaining();
+
+                if remaining > 0 {
+                    events.push(self.insert_bids(order, remaining));
+                }
+            }
+
+            IncomingSide::Sell => {
+                let mut iter = self.match_bids(
+                    amend.order_id,
+                    order.account_id,
+                    amend.qty,
+                    Some(Reverse(PriceKey(amend.price))),
+                );
+                events.extend(iter.by_ref());
+                let remaining = iter.remaining();
+
+                if remaining > 0 {
+                    events.push(self.insert_asks(order, remaining));
+                }
+            }
+        }
+
+        events
+    }
+
+    /// Whether an order on `side` at `price` would trade against the opposite best
+    pub fn crosses(&self, side: &IncomingSide, price: u64) -> bool {
+        match side {
+            IncomingSide::Buy => self.best_ask().is_some_and(|ask| ask.0 <= price),
+            IncomingSide::Sell => self.best_bid().is_some_and(|bid| bid.0.0 >= price),
+        }
+    }
+
+    /// Expire a DAY/GTD order that is due
+    /// Returns None if the order is gone or no longer carries this expiry
+    pub fn expire_order(&mut self, order_id: OrderId, expires_at: i64) -> Option<BookEvent> {
+        if self.get_order(order_id)?.expires_at != Some(expires_at) {
+            return None;
+        }
+
+        let order = self.remove_order(order_id)?;
+
+        Some(BookEvent::Expire(ExpireEvent {
+            order_id,
+            qty: order.total_qty(),
+            ts: self.now,
+        }))
+    }
+
+    /// Unlink an order from its price level and remove it from the book
+    fn remove_order(&mut self, order_id: OrderId) -> Option<RestingOrder> {
+        let idx = self.order_map.remove(&order_id)?;
+
         let price_key = PriceKey(self.orders[idx].price);
-        let qty = self.orders[idx].qty;
 
         let side = self.orders[idx].side.clone();
         let level = match side {
@@ -133,6 +328,8 @@ impl OrderBook {
         }
 
         level.total_orders -= 1;
+        level.total_qty -= self.orders[idx].qty as u64;
+        level.hidden_qty -= self.orders[idx].hidden_qty as u64;
 
         if level.head.is_none() {
             debug_assert_eq!(level.total_orders, 0);
@@ -146,44 +343,89 @@ impl OrderBook {
             }
         }
 
-        vec![BookEvent::Cancel(CancelEvent {
-            order_id,
-            qty,
-            ts: Utc::now().timestamp_micros(),
-        })]
+        Some(self.orders.remove(idx))
+    }
+
+    /// Cost of a taker order of `qty` on `side`, optionally capped at `limit_price`
+    /// Read-only, self-trade prevention is not taken into account (see `quote_for`)
+    pub fn quote(&self, side: &IncomingSide, qty: u32, limit_price: Option<u64>) -> FillQuote {
+        match side {
+            IncomingSide::Buy => self
+                .asks
+                .quote(qty as u64, limit_price.map(PriceKey).as_ref()),
+            IncomingSide::Sell => self.bids.quote(
+                qty as u64,
+                limit_price.map(|price| Reverse(PriceKey(price))).as_ref(),
+            ),
+        }
     }
 
+    /// Like `quote`, for a taker of `account_id` under the book's self-trade prevention
+    /// What an all-or-nothing order can rely on actually trading
+    pub fn quote_for(
+        &self,
+        side: &IncomingSide,
+        qty: u32,
+        limit_price: Option<u64>,
+        account_id: AccountId,
+    ) -> FillQuote {
+        let Some(stp_mode) = self.stp_mode else {
+            return self.quote(side, qty, limit_price);
+        };
+
+        match side {
+            IncomingSide::Buy => self.asks.quote_stp(
+                qty as u64,
+                limit_price.map(PriceKey).as_ref(),
+                &self.orders,
+                account_id,
+                stp_mode,
+            ),
+            IncomingSide::Sell => self.bids.quote_stp(
+                qty as u64,
+                limit_price.map(|price| Reverse(PriceKey(price))).as_ref(),
+                &self.orders,
+                account_id,
+                stp_mode,
+            ),
+        }
+    }
+
+    /// Sweep asks up to `price_limit` if given, otherwise the whole side
     #[inline]
-    pub fn match_market_buy(&mut self, order: &IncomingMarketOrder) -> MatchIter<'_, Asks> {
-        MatchIter::new(
-            &mut self.asks,
-            &mut self.orders,
-            &mut self.order_map,
+    pub fn match_market_buy(
+        &mut self,
+        order: &IncomingMarketOrder,
+        price_limit: Option<u64>,
+    ) -> MatchIter<'_, Asks> {
+        self.match_asks(
             order.order_id,
+            order.account_id,
             order.qty,
-            None,
+            price_limit.map(PriceKey),
         )
     }
 
+    /// Sweep bids down to `price_limit` if given, otherwise the whole side
     #[inline]
-    pub fn match_market_sell(&mut self, order: &IncomingMarketOrder) -> MatchIter<'_, Bids> {
-        MatchIter::new(
-            &mut self.bids,
-            &mut self.orders,
-            &mut self.order_map,
+    pub fn match_market_sell(
+        &mut self,
+        order: &IncomingMarketOrder,
+        price_limit: Option<u64>,
+    ) -> MatchIter<'_, Bids> {
+        self.match_bids(
             order.order_id,
+            order.account_id,
             order.qty,
-            None,
+            price_limit.map(|price| Reverse(PriceKey(price))),
         )
     }
 
     #[inline]
     pub fn match_limit_buy(&mut self, order: &IncomingLimitOrder) -> MatchIter<'_, Asks> {
-        MatchIter::new(
-            &mut self.asks,
-            &mut self.orders,
-            &mut self.order_map,
+        self.match_asks(
             order.order_id,
+            order.account_id,
             order.qty,
             Some(PriceKey(order.price)),
         )
@@ -191,14 +433,86 @@ impl OrderBook {
 
     #[inline]
     pub fn match_limit_sell(&mut self, order: &IncomingLimitOrder) -> MatchIter<'_, Bids> {
+        self.match_bids(
+            order.order_id,
+            order.account_id,
+            order.qty,
+            Some(Reverse(PriceKey(order.price))),
+        )
+    }
+
+    #[inline]
+    pub fn match_iceberg_buy(&mut self, order: &IncomingIcebergOrder) -> MatchIter<'_, Asks> {
+        self.match_asks(
+            order.order_id,
+            order.account_id,
+            order.total_qty,
+            Some(PriceKey(order.price)),
+        )
+    }
+
+    #[inline]
+    pub fn match_iceberg_sell(&mut self, order: &IncomingIcebergOrder) -> MatchIter<'_, Bids> {
+        self.match_bids(
+            order.order_id,
+            order.account_id,
+            order.total_qty,
+            Some(Reverse(PriceKey(order.price))),
+        )
+    }
+
+    /// Taker buying from the asks
+    #[inline]
+    fn match_asks(
+        &mut self,
+        order_id: OrderId,
+        account_id: AccountId,
+        qty: u32,
+        price_limit: Option<PriceKey>,
+    ) -> MatchIter<'_, Asks> {
+        MatchIter::new(
+            &mut self.asks,
+            &mut self.orders,
+            &mut self.order_map,
+            order_id,
+            qty,
+            price_limit,
+            self.now,
+        )
+        .with_stp(account_id, self.stp_mode)
+    }
+
+    /// Taker selling into the bids
+    #[inline]
+    fn match_bids(
+        &mut self,
+        order_id: OrderId,
+        account_id: AccountId,
+        qty: u32,
+        price_limit: Option<Reverse<PriceKey>>,
+    ) -> MatchIter<'_, Bids> {
         MatchIter::new(
             &mut self.bids,
             &mut self.orders,
             &mut self.order_map,
-            order.order_id,
-            order.qty,
-            Some(Reverse(PriceKey(order.price))),
+            order_id,
+            qty,
+            price_limit,
+            self.now,
         )
+        .with_stp(account_id, self.stp_mode)
+    }
+
+    /// Engine time used to stamp orders and events
+    #[inline]
+    pub fn set_time(&mut self, now: i64) {
+        self.now = now;
+    }
+
+    /// Self-trade prevention applied to every match, None allows self-trades
+    #[inline]
+    pub fn set_stp_mode(&mut self, stp_mode: Option<StpMode>) {
+        self.stp_mode = stp_mode;
     }
 
     /// Lookup an order index by OrderId
@@ -213,6 +527,24 @@ impl OrderBook {
         self.get_index(id).map(|idx| &self.orders[idx])
     }
 
+    /// FIFO position of a resting order at its price level, None if it is not resting
+    pub fn queue_position(&self, id: OrderId) -> Option<QueuePosition> {
+        let idx = self.get_index(id)?;
+
+        let mut position = QueuePosition {
+            index: 0,
+            qty_ahead: 0,
+        };
+        let mut current = self.orders[idx].prev;
+        while let Some(prev) = current {
+            position.index += 1;
+            position.qty_ahead += self.orders[prev].qty as u64;
+            current = self.orders[prev].prev;
+        }
+
+        Some(position)
+    }
+
     /// Mutable reference to order lookup by OrderId
     #[inline]
     pub fn get_order_mut(&mut self, id: OrderId) -> Option<&mut RestingOrder> {
@@ -220,6 +552,17 @@ impl OrderBook {
         Some(&mut self.orders[idx])
     }
 
+    /// Number of resting orders
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
     /// Best bid price
     #[inline]
     pub fn best_bid(&self) -> Option<&Reverse<PriceKey>> {
@@ -232,70 +575,95 @@ impl OrderBook {
         self.asks.levels.first_key_value().map(|(k, _)| k)
     }
 
-    pub fn print_book(&self) -> Vec<BookEvent> {
-        let checksum = self.checksum();
-
-        let output_string = format!(
-            "---- BIDS ----\n{}\n---- ASKS ----\n{}\nOrderBook checksum is: {}\n",
-            self.bids.print_levels(),
-            self.asks.print_levels(),
-            checksum
-        );
-
-        vec![BookEvent::BookSnapshot(output_string)]
+    /// Top `depth` levels on each side as (price, visible qty, order count)
+    pub fn depth(&self, depth: usize) -> BookDepth {
+        BookDepth {
+            bids: self.bids.depth(depth),
+            asks: self.asks.depth(depth),
+        }
     }
 
-    /// For checking equality of order book state via a checksum
-    fn checksum(&self) -> u64 {
-        let mut hasher = DefaultHasher::new();
-
-        // Bids
-        for (price, level) in &self.bids.levels {
-            price.hash(&mut hasher);
-            level.total_orders.hash(&mut hasher);
-
-            let mut current = level.head;
-
-            while let Some(idx) = current {
-                let order = &self.orders[idx];
-
-                // Hash logical order state only
-                order.order_id.hash(&mut hasher);
-                order.qty.hash(&mut hasher);
-                order.side.hash(&mut hasher);
-
-                current = order.next;
-            }
+    /// Top `depth` price buckets `bucket` ticks wide on each side, see `BookSide::bucketed_depth`
+    pub fn bucketed_depth(&self, depth: usize, bucket: u64) -> BookDepth {
+        BookDepth {
+            bids: self.bids.bucketed_depth(depth, bucket),
+            asks: self.asks.bucketed_depth(depth, bucket),
         }
+    }
 
-        // Asks
-        for (price, level) in &self.asks.levels {
-            price.hash(&mut hasher);
-            level.total_orders.hash(&mut hasher);
-
-            let mut current = level.head;
+    /// Structured state of the whole book, optionally with every resting order
+    pub fn snapshot(&self, include_orders: bool) -> BookSnapshot {
+        BookSnapshot {
+            symbol: DEFAULT_SYMBOL, // The engine tags snapshots with their market
+            bids: self.bids.level_snapshots(&self.orders, include_orders),
+            asks: self.asks.level_snapshots(&self.orders, include_orders),
+            checksum: self.checksum(),
+            checksum_version: CHECKSUM_VERSION,
+            crc_depth: CRC_DEPTH as u32,
+            top_levels_crc: self.top_levels_crc(CRC_DEPTH),
+        }
+    }
 
-            while let Some(idx) = current {
-                let order = &self.orders[idx];
+    /// Stable checksum of the full book state, see `CHECKSUM_VERSION` for the layout
+    pub fn checksum(&self) -> u64 {
+        let mut hasher = Fnv1a::default();
+        hash_side(&self.bids, &self.orders, 0, &mut hasher);
+        hash_side(&self.asks, &self.orders, 1, &mut hasher);
+        hasher.finish()
+    }
 
-                // Hash logical order state only
-                order.order_id.hash(&mut hasher);
-                order.qty.hash(&mut hasher);
-                order.side.hash(&mut hasher);
+    /// CRC32 over the top `depth` levels of each side, for market-data clients
+    ///
+    /// Computed like common crypto venue feeds: CRC-32 of the interleaved
+    /// `bid_price:bid_qty:ask_price:ask_qty:...` string, quantities being visible size.
+    pub fn top_levels_crc(&self, depth: usize) -> u32 {
+        let bids = self.bids.top_levels(depth);
+        let asks = self.asks.top_levels(depth);
+        crc32(top_levels_string(&bids, &asks).as_bytes())
+    }
+}
 
-                current = order.next;
-            }
+/// Feed one side into the book checksum, best level first and orders in FIFO order
+fn hash_side<OrderSide: Side>(
+    side: &BookSide<OrderSide>,
+    orders: &Slab<RestingOrder>,
+    tag: u8,
+    hasher: &mut Fnv1a,
+) {
+    hasher.write_u8(tag);
+    hasher.write_u64(side.levels.len() as u64);
+
+    for (key, level) in &side.levels {
+        hasher.write_u64(OrderSide::key_to_price(key.clone()).0);
+        hasher.write_u64(level.total_orders);
+
+        let mut current = level.head;
+        while let Some(idx) = current {
+            let order = &orders[idx];
+
+            // Logical order state only
+            hasher.write_u64(order.order_id);
+            hasher.write_u32(order.qty);
+            hasher.write_u32(order.hidden_qty);
+            hasher.write_u64(order.account_id);
+            hasher.write_u8(order.display_qty.is_some() as u8);
+            hasher.write_u32(order.display_qty.unwrap_or_default());
+            hasher.write_u8(order.expires_at.is_some() as u8);
+            hasher.write_u64(order.expires_at.unwrap_or_default() as u64);
+            hasher.write_u8(order.post_only as u8);
+
+            current = order.next;
         }
-
-        hasher.finish()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::data::book_depth::DepthLevel;
     use crate::data::book_event::MatchEvent;
-    use chrono::Utc;
+    use crate::data::fill_quote::QuoteFill;
+    use crate::data::order_types::TimeInForce;
 
     fn resting(id: u64, price: u64, qty: u32, side: IncomingSide) -> RestingOrder {
         RestingOrder {
@@ -303,9 +671,21 @@ mod tests {
             price,
             qty,
             side,
+            account_id: 0,
+            display_qty: None,
+            hidden_qty: 0,
+            expires_at: None,
+            post_only: false,
             next: None,
             prev: None,
-            ts: Utc::now().timestamp_micros(),
+            ts: 0,
+        }
+    }
+
+    fn iceberg(id: u64, price: u64, display: u32, total: u32, side: IncomingSide) -> RestingOrder {
+        RestingOrder {
+            display_qty: Some(display),
+            ..resting(id, price, total, side)
         }
     }
 
@@ -314,6 +694,8 @@ mod tests {
             order_id: id,
             qty,
             side,
+            account_id: 0,
+            symbol: 0,
         }
     }
 
@@ -323,6 +705,10 @@ mod tests {
             price,
             qty,
             side,
+            account_id: 0,
+            post_only: None,
+            time_in_force: TimeInForce::Gtc,
+            symbol: 0,
         }
     }
 
@@ -334,13 +720,27 @@ mod tests {
     }
 
     fn assert_book_consistency(book: &OrderBook) {
-        for level in book.bids.levels.values() {
-            assert!(level.head.is_some());
-            assert!(level.total_orders > 0);
+        // Every slab entry is reachable through the map
+        assert_eq!(book.orders.len(), book.order_map.len());
+        for (id, idx) in &book.order_map {
+            assert_eq!(book.orders[*idx].order_id, *id);
         }
-        for level in book.asks.levels.values() {
+
+        let levels = book.bids.levels.values().chain(book.asks.levels.values());
+        for level in levels {
             assert!(level.head.is_some());
             assert!(level.total_orders > 0);
+
+            // Running totals match a walk of the level
+            let (mut qty, mut hidden_qty) = (0, 0);
+            let mut current = level.head;
+            while let Some(idx) = current {
+                qty += book.orders[idx].qty as u64;
+                hidden_qty += book.orders[idx].hidden_qty as u64;
+                current = book.orders[idx].next;
+            }
+            assert_eq!(level.total_qty, qty);
+            assert_eq!(level.hidden_qty, hidden_qty);
         }
     }
 
@@ -513,7 +913,7 @@ mod tests {
 
         // Market buy for qty 8
         let fills: Vec<_> = book
-            .match_market_buy(&market(4, 8, IncomingSide::Buy))
+            .match_market_buy(&market(4, 8, IncomingSide::Buy), None)
             .collect();
 
         // Should consume:
@@ -546,7 +946,7 @@ mod tests {
 
         // Fully consume
         let fills: Vec<_> = book
-            .match_market_buy(&market(2, 5, IncomingSide::Buy))
+            .match_market_buy(&market(2, 5, IncomingSide::Buy), None)
             .collect();
 
         assert_eq!(fills.len(), 1);
@@ -563,7 +963,7 @@ mod tests {
         book.insert_asks(resting(3, 102, 5, IncomingSide::Sell), 5);
 
         let fills: Vec<_> = book
-            .match_market_buy(&market(4, 12, IncomingSide::Buy))
+            .match_market_buy(&market(4, 12, IncomingSide::Buy), None)
             .collect();
 
         // Should match strictly price-time priority:
@@ -578,4 +978,431 @@ mod tests {
         assert_eq!(match_event(&fills[2]).qty, 2);
         assert_book_consistency(&book);
     }
+
+    #[test]
+    fn test_market_order_stops_at_price_limit() {
+        let mut book = OrderBook::default();
+
+        book.insert_asks(resting(1, 100, 5, IncomingSide::Sell), 5);
+        book.insert_asks(resting(2, 101, 5, IncomingSide::Sell), 5);
+        book.insert_asks(resting(3, 102, 5, IncomingSide::Sell), 5);
+
+        let mut iter = book.match_market_buy(&market(4, 12, IncomingSide::Buy), Some(101));
+        let fills: Vec<_> = iter.by_ref().collect();
+
+        assert_eq!(fills.len(), 2);
+        assert_eq!(match_event(&fills[1]).price, 101);
+        assert_eq!(iter.remaining(), 2);
+        assert_eq!(book.best_ask(), Some(&PriceKey(102)));
+        assert_book_consistency(&book);
+    }
+
+    #[test]
+    fn test_iceberg_rests_display_slice() {
+        let mut book = OrderBook::default();
+
+        let event = book.insert_asks(iceberg(1, 100, 5, 20, IncomingSide::Sell), 20);
+
+        match event {
+            BookEvent::Insert(insert) => {
+                assert_eq!(insert.qty, 5);
+                assert_eq!(insert.hidden_qty, 15);
+            }
+            _ => panic!("Expected InsertEvent"),
+        }
+
+        let order = book.get_order(1).unwrap();
+        assert_eq!(order.qty, 5);
+        assert_eq!(order.hidden_qty, 15);
+
+        // Cancel reports the full remaining size including the reserve
+        match &book.cancel_order(1)[0] {
+            BookEvent::Cancel(cancel) => assert_eq!(cancel.qty, 20),
+            _ => panic!("Expected CancelEvent"),
+        }
+        assert!(book.asks.levels.is_empty());
+        assert_book_consistency(&book);
+    }
+
+    #[test]
+    fn test_iceberg_replenish_loses_priority() {
+        let mut book = OrderBook::default();
+
+        book.insert_asks(iceberg(1, 100, 5, 12, IncomingSide::Sell), 12);
+        book.insert_asks(resting(2, 100, 5, IncomingSide::Sell), 5);
+
+        // Consume the visible slice of the iceberg plus 2 from the next order
+        let events: Vec<_> = book
+            .match_market_buy(&market(3, 7, IncomingSide::Buy), None)
+            .collect();
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(match_event(&events[0]).maker, 1);
+        assert_eq!(match_event(&events[0]).qty, 5);
+
+        match &events[1] {
+            BookEvent::Replenish(replenish) => {
+                assert_eq!(replenish.order_id, 1);
+                assert_eq!(replenish.qty, 5);
+                assert_eq!(replenish.hidden_qty, 2);
+            }
+            _ => panic!("Expected ReplenishEvent"),
+        }
+
+        // Order 2 is now ahead of the replenished iceberg
+        assert_eq!(match_event(&events[2]).maker, 2);
+        assert_eq!(match_event(&events[2]).qty, 2);
+
+        let level = book.asks.levels.get(&PriceKey(100)).unwrap();
+        assert_eq!(book.orders[level.head.unwrap()].order_id, 2);
+        assert_eq!(book.orders[level.tail.unwrap()].order_id, 1);
+        assert_eq!((level.total_qty, level.hidden_qty), (8, 2));
+        assert_book_consistency(&book);
+
+        // Final slice is only what is left in reserve
+        let events: Vec<_> = book
+            .match_market_buy(&market(4, 10, IncomingSide::Buy), None)
+            .collect();
+        assert_eq!(events.len(), 4);
+        assert_eq!(match_event(&events[3]).maker, 1);
+        assert_eq!(match_event(&events[3]).qty, 2);
+        assert!(book.asks.levels.is_empty());
+        assert!(book.order_map.is_empty());
+    }
+
+    #[test]
+    fn test_decrement_and_cancel_iceberg_is_one_event() {
+        let mut book = OrderBook::default();
+        book.set_stp_mode(Some(StpMode::DecrementAndCancel));
+        book.insert_asks(iceberg(1, 100, 5, 20, IncomingSide::Sell), 20);
+
+        // Decrement comes out of the reserve, the visible slice keeps its place
+        let events: Vec<_> = book
+            .match_market_buy(&market(2, 12, IncomingSide::Buy), None)
+            .collect();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            BookEvent::SelfTrade(event) => assert_eq!((event.maker_qty, event.taker_qty), (12, 12)),
+            _ => panic!("Expected SelfTradeEvent"),
+        }
+        let maker = book.get_order(1).unwrap();
+        assert_eq!((maker.qty, maker.hidden_qty), (5, 3));
+        assert_book_consistency(&book);
+
+        // A bigger taker cancels the whole iceberg in one go
+        let mut iter = book.match_market_buy(&market(3, 30, IncomingSide::Buy), None);
+        let events: Vec<_> = iter.by_ref().collect();
+        assert_eq!(iter.remaining(), 22);
+        assert_eq!(events.len(), 1);
+        assert!(book.get_order(1).is_none());
+        assert!(book.asks.levels.is_empty());
+        assert_book_consistency(&book);
+    }
+
+    #[test]
+    fn test_level_totals_follow_self_trade_cancel() {
+        let mut book = OrderBook::default();
+        book.set_stp_mode(Some(StpMode::CancelOldest));
+
+        book.insert_asks(iceberg(1, 100, 5, 12, IncomingSide::Sell), 12);
+        let other_account = RestingOrder {
+            account_id: 7,
+            ..resting(2, 100, 4, IncomingSide::Sell)
+        };
+        book.insert_asks(other_account, 4);
+        let level = book.asks.levels.get(&PriceKey(100)).unwrap();
+        assert_eq!((level.total_qty, level.hidden_qty), (9, 7));
+
+        // Same account -> the iceberg is cancelled, reserve included, then order 2 trades
+        let events: Vec<_> = book
+            .match_market_buy(&market(3, 1, IncomingSide::Buy), None)
+            .collect();
+        assert!(matches!(events[0], BookEvent::SelfTrade(_)));
+        assert_eq!(match_event(&events[1]).maker, 2);
+
+        let level = book.asks.levels.get(&PriceKey(100)).unwrap();
+        assert_eq!((level.total_qty, level.hidden_qty), (3, 0));
+        assert_book_consistency(&book);
+    }
+
+    fn modify(id: u64, price: u64, qty: u32) -> IncomingModifyOrder {
+        IncomingModifyOrder {
+            order_id: id,
+            price,
+            qty,
+            symbol: 0,
+        }
+    }
+
+    #[test]
+    fn test_amend_reduce_keeps_priority() {
+        let mut book = OrderBook::default();
+
+        book.insert_bids(resting(1, 100, 10, IncomingSide::Buy), 10);
+        book.insert_bids(resting(2, 100, 10, IncomingSide::Buy), 10);
+
+        let events = book.amend_order(&modify(1, 100, 4));
+
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            BookEvent::Amend(amend) => {
+                assert_eq!(amend.old_qty, 10);
+                assert_eq!(amend.qty, 4);
+            }
+            _ => panic!("Expected AmendEvent"),
+        }
+
+        let level = book.bids.levels.get(&Reverse(PriceKey(100))).unwrap();
+        assert_eq!(book.orders[level.head.unwrap()].order_id, 1);
+        assert_eq!(book.orders[level.head.unwrap()].qty, 4);
+        assert_book_consistency(&book);
+    }
+
+    #[test]
+    fn test_amend_increase_moves_to_tail() {
+        let mut book = OrderBook::default();
+
+        book.insert_bids(resting(1, 100, 10, IncomingSide::Buy), 10);
+        book.insert_bids(resting(2, 100, 10, IncomingSide::Buy), 10);
+
+        let events = book.amend_order(&modify(1, 100, 15));
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], BookEvent::Amend(_)));
+        assert!(matches!(events[1], BookEvent::Insert(_)));
+
+        let level = book.bids.levels.get(&Reverse(PriceKey(100))).unwrap();
+        assert_eq!(level.total_orders, 2);
+        assert_eq!(book.orders[level.head.unwrap()].order_id, 2);
+        assert_eq!(book.orders[level.tail.unwrap()].order_id, 1);
+        assert_eq!(book.orders[level.tail.unwrap()].qty, 15);
+        assert_book_consistency(&book);
+    }
+
+    #[test]
+    fn test_amend_price_crosses_and_matches() {
+        let mut book = OrderBook::default();
+
+        book.insert_bids(resting(1, 100, 10, IncomingSide::Buy), 10);
+        book.insert_asks(resting(2, 102, 4, IncomingSide::Sell), 4);
+
+        let events = book.amend_order(&modify(1, 102, 10));
+
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], BookEvent::Amend(_)));
+        assert_eq!(match_event(&events[1]).maker, 2);
+        assert_eq!(match_event(&events[1]).taker, 1);
+        assert_eq!(match_event(&events[1]).qty, 4);
+        assert!(matches!(events[2], BookEvent::Insert(_)));
+
+        assert!(book.asks.levels.is_empty());
+        assert_eq!(book.best_bid().unwrap().0, PriceKey(102));
+        assert_eq!(book.get_order(1).unwrap().qty, 6);
+        assert_eq!(book.bids.levels.len(), 1);
+        assert_book_consistency(&book);
+    }
+
+    #[test]
+    fn test_amend_post_only_never_crosses() {
+        let mut book = OrderBook::default();
+
+        let post_only = RestingOrder {
+            post_only: true,
+            ..resting(1, 100, 10, IncomingSide::Buy)
+        };
+        book.insert_bids(post_only, 10);
+        book.insert_asks(resting(2, 102, 4, IncomingSide::Sell), 4);
+
+        let events = book.amend_order(&modify(1, 102, 10));
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            BookEvent::Reject(reject) => {
+                assert_eq!(reject.reason, RejectReason::PostOnlyWouldCross)
+            }
+            _ => panic!("Expected RejectEvent"),
+        }
+        assert_eq!(book.get_order(1).unwrap().price, 100);
+        assert_eq!(book.get_order(2).unwrap().qty, 4);
+
+        // Repricing without crossing still works
+        let events = book.amend_order(&modify(1, 101, 10));
+        assert!(matches!(events[1], BookEvent::Insert(_)));
+        assert!(book.get_order(1).unwrap().post_only);
+        assert_book_consistency(&book);
+    }
+
+    #[test]
+    fn test_checksum_is_pinned() {
+        let mut book = OrderBook::default();
+        assert_eq!(book.checksum(), 0xe47e_2054_331b_ad1a);
+
+        book.insert_bids(resting(1, 100, 5, IncomingSide::Buy), 5);
+        book.insert_bids(resting(2, 100, 3, IncomingSide::Buy), 3);
+        book.insert_bids(resting(3, 99, 7, IncomingSide::Buy), 7);
+        book.insert_asks(resting(4, 101, 2, IncomingSide::Sell), 2);
+
+        // Changing this value means changing the algorithm, bump CHECKSUM_VERSION
+        assert_eq!(book.checksum(), 0x0a2e_b933_8077_bc4a);
+
+        // Re-adding the only order of a level gives back the same state
+        let before = book.checksum();
+        book.cancel_order(3);
+        book.insert_bids(resting(3, 99, 7, IncomingSide::Buy), 7);
+        assert_eq!(book.checksum(), before);
+
+        // Owner, display size and expiry are part of the state
+        for changed in [
+            RestingOrder {
+                account_id: 9,
+                ..resting(3, 99, 7, IncomingSide::Buy)
+            },
+            iceberg(3, 99, 7, 7, IncomingSide::Buy),
+            RestingOrder {
+                expires_at: Some(50),
+                ..resting(3, 99, 7, IncomingSide::Buy)
+            },
+        ] {
+            book.cancel_order(3);
+            book.insert_bids(changed, 7);
+            assert_ne!(book.checksum(), before);
+        }
+        book.cancel_order(3);
+        book.insert_bids(resting(3, 99, 7, IncomingSide::Buy), 7);
+
+        // FIFO order is part of the state
+        book.cancel_order(1);
+        book.insert_bids(resting(1, 100, 5, IncomingSide::Buy), 5);
+        assert_ne!(book.checksum(), before);
+    }
+
+    #[test]
+    fn test_top_levels_crc() {
+        let mut book = OrderBook::default();
+        book.insert_bids(resting(1, 100, 5, IncomingSide::Buy), 5);
+        book.insert_bids(resting(2, 100, 3, IncomingSide::Buy), 3);
+        book.insert_bids(resting(3, 99, 7, IncomingSide::Buy), 7);
+        book.insert_asks(resting(4, 101, 2, IncomingSide::Sell), 2);
+
+        assert_eq!(book.top_levels_crc(25), crc32(b"100:8:101:2:99:7"));
+        assert_eq!(book.top_levels_crc(1), crc32(b"100:8:101:2"));
+    }
+
+    #[test]
+    fn test_queue_position() {
+        let mut book = OrderBook::default();
+        book.insert_asks(resting(1, 100, 5, IncomingSide::Sell), 5);
+        book.insert_asks(iceberg(2, 100, 2, 9, IncomingSide::Sell), 9);
+        book.insert_asks(resting(3, 100, 4, IncomingSide::Sell), 4);
+        book.insert_asks(resting(4, 101, 1, IncomingSide::Sell), 1);
+
+        let position = |index, qty_ahead| Some(QueuePosition { index, qty_ahead });
+        assert_eq!(book.queue_position(1), position(0, 0));
+        // Only the iceberg's visible slice is ahead
+        assert_eq!(book.queue_position(3), position(2, 7));
+        assert_eq!(book.queue_position(4), position(0, 0));
+        assert_eq!(book.queue_position(99), None);
+
+        // Fills and cancels ahead move the order up
+        book.match_market_buy(&market(5, 6, IncomingSide::Buy), None)
+            .count();
+        book.cancel_order(2);
+        assert_eq!(book.queue_position(3), position(0, 0));
+    }
+
+    #[test]
+    fn test_quote_does_not_touch_the_book() {
+        let mut book = OrderBook::default();
+        book.insert_asks(resting(1, 100, 5, IncomingSide::Sell), 5);
+        book.insert_asks(iceberg(2, 101, 2, 6, IncomingSide::Sell), 6);
+        book.insert_asks(resting(3, 103, 10, IncomingSide::Sell), 10);
+        let checksum = book.checksum();
+
+        let quote = book.quote(&IncomingSide::Buy, 14, None);
+        assert_eq!(
+            quote.fills,
+            vec![
+                QuoteFill { price: 100, qty: 5 },
+                QuoteFill { price: 101, qty: 6 },
+                QuoteFill { price: 103, qty: 3 },
+            ]
+        );
+        assert_eq!(quote.filled_qty, 14);
+        assert_eq!(quote.worst_price, Some(103));
+        assert!(quote.is_complete());
+        assert_eq!(quote.average_price(), Some(1415.0 / 14.0));
+        assert_eq!(book.checksum(), checksum);
+
+        // The limit price stops the sweep
+        let quote = book.quote(&IncomingSide::Buy, 14, Some(101));
+        assert_eq!((quote.filled_qty, quote.unfilled_qty), (11, 3));
+        assert_eq!(quote.worst_price, Some(101));
+
+        // Same fills as actually matching
+        let filled: u32 = book
+            .match_market_buy(&market(4, 14, IncomingSide::Buy), None)
+            .filter_map(|event| match event {
+                BookEvent::Match(fill) => Some(fill.qty),
+                _ => None,
+            })
+            .sum();
+        assert_eq!(filled, 14);
+
+        let quote = book.quote(&IncomingSide::Sell, 1, None);
+        assert_eq!(quote.average_price(), None);
+        assert_eq!(quote.unfilled_qty, 1);
+    }
+
+    #[test]
+    fn test_depth_and_buckets() {
+        let mut book = OrderBook::default();
+        book.insert_bids(resting(1, 100, 5, IncomingSide::Buy), 5);
+        book.insert_bids(resting(2, 100, 3, IncomingSide::Buy), 3);
+        book.insert_bids(resting(3, 98, 7, IncomingSide::Buy), 7);
+        book.insert_bids(resting(4, 94, 1, IncomingSide::Buy), 1);
+        book.insert_asks(iceberg(5, 101, 2, 10, IncomingSide::Sell), 10);
+        book.insert_asks(resting(6, 104, 4, IncomingSide::Sell), 4);
+
+        let level = |price, qty, order_count| DepthLevel {
+            price,
+            qty,
+            order_count,
+        };
+
+        let depth = book.depth(2);
+        assert_eq!(depth.bids, vec![level(100, 8, 2), level(98, 7, 1)]);
+        // Iceberg reserve is not shown
+        assert_eq!(depth.asks, vec![level(101, 2, 1), level(104, 4, 1)]);
+        assert_eq!(book.depth(0), BookDepth::default());
+
+        // Bids round down and asks round up to the bucket
+        let buckets = book.bucketed_depth(2, 5);
+        assert_eq!(buckets.bids, vec![level(100, 8, 2), level(95, 7, 1)]);
+        assert_eq!(buckets.asks, vec![level(105, 6, 2)]);
+        assert_eq!(book.bucketed_depth(10, 1), book.depth(10));
+    }
+
+    #[test]
+    fn test_snapshot_aggregates_levels() {
+        let mut book = OrderBook::default();
+        book.insert_bids(resting(1, 100, 5, IncomingSide::Buy), 5);
+        book.insert_bids(resting(2, 100, 3, IncomingSide::Buy), 3);
+        book.insert_bids(resting(3, 99, 7, IncomingSide::Buy), 7);
+
+        let snapshot = book.snapshot(true);
+        assert!(snapshot.asks.is_empty());
+        assert_eq!(snapshot.bids.len(), 2);
+        assert_eq!(snapshot.bids[0].price, 100);
+        assert_eq!(snapshot.bids[0].qty, 8);
+        assert_eq!(snapshot.bids[0].order_count, 2);
+        assert_eq!(snapshot.checksum, book.checksum());
+
+        let fifo: Vec<u64> = snapshot.bids[0]
+            .orders
+            .iter()
+            .flatten()
+            .map(|order| order.order_id)
+            .collect();
+        assert_eq!(fifo, vec![1, 2]);
+
+        assert!(book.snapshot(false).bids[0].orders.is_none());
+    }
 }
@@ -0,0 +1,115 @@
+use crate::data::order_types::IncomingSide;
+use crate::data::orders::inbound_orders::IncomingStopOrder;
+use crate::data::orders::resting_orders::OrderId;
+use crate::orderbook::util::price_key::PriceKey;
+
+use rustc_hash::FxHashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Pending stop orders keyed by trigger price
+/// Buy stops fire when the last trade is at or above the trigger, sell stops at or below
+#[derive(Default)]
+pub struct StopBook {
+    buys: BTreeMap<PriceKey, VecDeque<IncomingStopOrder>>, // Lowest trigger first
+    sells: BTreeMap<Reverse<PriceKey>, VecDeque<IncomingStopOrder>>, // Highest trigger first
+    index: FxHashMap<OrderId, (IncomingSide, u64)>,
+}
+
+impl StopBook {
+    pub fn insert(&mut self, order: IncomingStopOrder) {
+        self.index
+            .insert(order.order_id, (order.side.clone(), order.trigger_price));
+
+        match order.side {
+            IncomingSide::Buy => self
+                .buys
+                .entry(PriceKey(order.trigger_price))
+                .or_default()
+                .push_back(order),
+            IncomingSide::Sell => self
+                .sells
+                .entry(Reverse(PriceKey(order.trigger_price)))
+                .or_default()
+                .push_back(order),
+        }
+    }
+
+    /// Remove a pending stop order, None if it doesn't exist
+    pub fn cancel(&mut self, order_id: OrderId) -> Option<IncomingStopOrder> {
+        let (side, trigger_price) = self.index.remove(&order_id)?;
+
+        match side {
+            IncomingSide::Buy => remove_from(&mut self.buys, PriceKey(trigger_price), order_id),
+            IncomingSide::Sell => {
+                remove_from(&mut self.sells, Reverse(PriceKey(trigger_price)), order_id)
+            }
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, order_id: OrderId) -> bool {
+        self.index.contains_key(&order_id)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Every pending stop in release order: buys from the lowest trigger, then sells
+    /// from the highest, FIFO within a trigger
+    pub fn iter(&self) -> impl Iterator<Item = &IncomingStopOrder> {
+        self.buys
+            .values()
+            .flatten()
+            .chain(self.sells.values().flatten())
+    }
+
+    /// Take every stop triggered by `last_price`
+    /// Buys come first from the lowest trigger, then sells from the highest, FIFO within a trigger
+    pub fn take_triggered(&mut self, last_price: u64) -> Vec<IncomingStopOrder> {
+        let mut triggered = vec![];
+
+        while let Some(entry) = self.buys.first_entry() {
+            if entry.key().0 > last_price {
+                break;
+            }
+            triggered.extend(entry.remove());
+        }
+
+        while let Some(entry) = self.sells.first_entry() {
+            if entry.key().0.0 < last_price {
+                break;
+            }
+            triggered.extend(entry.remove());
+        }
+
+        for order in &triggered {
+            self.index.remove(&order.order_id);
+        }
+
+        triggered
+    }
+}
+
+fn remove_from<K: Ord>(
+    levels: &mut BTreeMap<K, VecDeque<IncomingStopOrder>>,
+    key: K,
+    order_id: OrderId,
+) -> Option<IncomingStopOrder> {
+    let queue = levels.get_mut(&key)?;
+    let position = queue.iter().position(|order| order.order_id == order_id)?;
+    let order = queue.remove(position);
+
+    if queue.is_empty() {
+        levels.remove(&key);
+    }
+
+    order
+}
@@ -1,5 +1,11 @@
+use crate::data::book_depth::DepthLevel;
+use crate::data::book_snapshot::{LevelSnapshot, OrderSnapshot};
+use crate::data::fill_quote::{FillQuote, QuoteFill};
+use crate::data::order_types::StpMode;
+use crate::data::orders::resting_orders::{AccountId, RestingOrder};
 use crate::data::price_level::PriceLevel;
 use crate::orderbook::util::side::Side;
+use slab::Slab;
 use std::collections::BTreeMap;
 
 #[derive(Debug)]
@@ -20,17 +26,180 @@ impl<OrderSide: Side> BookSide<OrderSide> {
         self.levels.entry(price.into()).or_default()
     }
 
-    pub fn print_levels(&self) -> String {
-        let mut out = String::new();
+    /// Every level best first, with each level's orders in FIFO order if `include_orders`
+    pub fn level_snapshots(
+        &self,
+        orders: &Slab<RestingOrder>,
+        include_orders: bool,
+    ) -> Vec<LevelSnapshot> {
+        self.levels
+            .iter()
+            .map(|(key, level)| {
+                // Only walk the level when the orders themselves are wanted
+                let orders = include_orders.then(|| {
+                    std::iter::successors(level.head, |idx| orders[*idx].next)
+                        .map(|idx| {
+                            let order = &orders[idx];
+                            OrderSnapshot {
+                                order_id: order.order_id,
+                                qty: order.qty,
+                                hidden_qty: order.hidden_qty,
+                                account_id: order.account_id,
+                                ts: order.ts,
+                            }
+                        })
+                        .collect()
+                });
+
+                LevelSnapshot {
+                    price: OrderSide::key_to_price(key.clone()).0,
+                    qty: level.total_qty,
+                    hidden_qty: level.hidden_qty,
+                    order_count: level.total_orders,
+                    orders,
+                }
+            })
+            .collect()
+    }
+
+    /// Fills a taker of `qty` would get sweeping this side up to `limit`, without matching
+    /// Iceberg reserve counts, it refills at the same level while the taker is still there
+    pub fn quote(&self, qty: u64, limit: Option<&OrderSide::Key>) -> FillQuote {
+        let mut quote = FillQuote {
+            unfilled_qty: qty,
+            ..FillQuote::default()
+        };
 
         for (key, level) in &self.levels {
-            let price = OrderSide::key_to_price(key.clone());
-            out.push_str(&format!(
-                "Price: {} | Orders: {}\n",
-                price, level.total_orders
-            ));
+            if quote.unfilled_qty == 0 {
+                break;
+            }
+            if let Some(limit) = limit
+                && OrderSide::compare_price(key, limit)
+            {
+                break;
+            }
+
+            let price = OrderSide::key_to_price(key.clone()).0;
+            take(&mut quote, price, level.total_qty + level.hidden_qty);
         }
 
-        out
+        quote
+    }
+
+    /// Like `quote`, for a taker of `account_id` under self-trade prevention `stp_mode`
+    ///
+    /// Orders of the same account are skipped under CancelOldest. Any other mode stops the
+    /// taker at the first one, once the visible orders ahead of it are used up (icebergs
+    /// among them refill behind it).
+    pub fn quote_stp(
+        &self,
+        qty: u64,
+        limit: Option<&OrderSide::Key>,
+        orders: &Slab<RestingOrder>,
+        account_id: AccountId,
+        stp_mode: StpMode,
+    ) -> FillQuote {
+        let mut quote = FillQuote {
+            unfilled_qty: qty,
+            ..FillQuote::default()
+        };
+
+        for (key, level) in &self.levels {
+            if quote.unfilled_qty == 0 {
+                break;
+            }
+            if let Some(limit) = limit
+                && OrderSide::compare_price(key, limit)
+            {
+                break;
+            }
+
+            let (mut total, mut visible, mut blocked) = (0, 0, false);
+            for idx in std::iter::successors(level.head, |idx| orders[*idx].next) {
+                let order = &orders[idx];
+                if order.account_id == account_id {
+                    if stp_mode == StpMode::CancelOldest {
+                        continue;
+                    }
+                    blocked = true;
+                    break;
+                }
+                total += order.total_qty() as u64;
+                visible += order.qty as u64;
+            }
+
+            let price = OrderSide::key_to_price(key.clone()).0;
+            take(&mut quote, price, if blocked { visible } else { total });
+            if blocked {
+                break;
+            }
+        }
+
+        quote
+    }
+
+    /// Best `depth` levels with their visible qty and order count
+    pub fn depth(&self, depth: usize) -> Vec<DepthLevel> {
+        self.levels
+            .iter()
+            .take(depth)
+            .map(|(key, level)| DepthLevel {
+                price: OrderSide::key_to_price(key.clone()).0,
+                qty: level.total_qty,
+                order_count: level.total_orders,
+            })
+            .collect()
+    }
+
+    /// Best `depth` buckets of `bucket` ticks, each level merged into the bucket holding it
+    /// Bids round down and asks round up, so a bucket never looks better than its levels
+    pub fn bucketed_depth(&self, depth: usize, bucket: u64) -> Vec<DepthLevel> {
+        let bucket = bucket.max(1);
+        let mut buckets: Vec<DepthLevel> = Vec::with_capacity(depth);
+
+        for (key, level) in &self.levels {
+            let price = OrderSide::bucket_price(OrderSide::key_to_price(key.clone()).0, bucket);
+            // Levels come best first, so a bucket's levels are contiguous
+            if let Some(last) = buckets.last_mut()
+                && last.price == price
+            {
+                last.qty += level.total_qty;
+                last.order_count += level.total_orders;
+                continue;
+            }
+            if buckets.len() == depth {
+                break;
+            }
+            buckets.push(DepthLevel {
+                price,
+                qty: level.total_qty,
+                order_count: level.total_orders,
+            });
+        }
+
+        buckets
+    }
+
+    /// Best `depth` levels as (price, visible qty)
+    pub fn top_levels(&self, depth: usize) -> Vec<(u64, u64)> {
+        self.levels
+            .iter()
+            .take(depth)
+            .map(|(key, level)| (OrderSide::key_to_price(key.clone()).0, level.total_qty))
+            .collect()
+    }
+}
+
+/// Fill up to `available` at `price`, nothing if the level has nothing left for the taker
+fn take(quote: &mut FillQuote, price: u64, available: u64) {
+    let traded = quote.unfilled_qty.min(available);
+    if traded == 0 {
+        return;
     }
+    quote.fills.push(QuoteFill { price, qty: traded });
+    quote.filled_qty += traded;
+    quote.notional += price as u128 * traded as u128;
+    quote.worst_price = Some(price);
+    quote.unfilled_qty -= traded;
 }
@@ -0,0 +1,145 @@
+//! Stable checksums over book state
+//!
+//! Both algorithms are fixed here instead of relying on std hashers, whose output may
+//! change between Rust releases. Changing what goes into the book checksum requires
+//! bumping `CHECKSUM_VERSION`.
+
+/// Version of the canonical book checksum, recorded next to it in snapshots
+///
+/// v2: FNV-1a 64 over, for bids (best first) then asks (best first):
+/// side byte (0 bids, 1 asks), level count (u64), then per level price (u64) and
+/// order count (u64) followed by every order in FIFO order as order id (u64),
+/// visible qty (u32), hidden qty (u32), account id (u64), display qty and expiry
+/// (each a presence byte then u32/i64, zero when absent) and post-only byte.
+/// All integers little-endian.
+///
+/// v1 stopped after hidden qty.
+pub const CHECKSUM_VERSION: u32 = 2;
+
+/// Levels per side covered by the top-of-book CRC
+pub const CRC_DEPTH: usize = 25;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a
+pub struct Fnv1a {
+    state: u64,
+}
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self {
+            state: FNV_OFFSET_BASIS,
+        }
+    }
+}
+
+impl Fnv1a {
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= *byte as u64;
+            self.state = self.state.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    #[inline]
+    pub fn write_u8(&mut self, value: u8) {
+        self.write(&[value]);
+    }
+
+    #[inline]
+    pub fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    #[inline]
+    pub fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    #[inline]
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+// CRC-32 (IEEE 802.3, reflected polynomial), as used by zlib and exchange feeds
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Exchange-style checksum string over the top levels of each side
+///
+/// Levels are interleaved best first as `bid_price:bid_qty:ask_price:ask_qty`, all joined
+/// with `:`. Once one side runs out only the other side's levels are appended.
+pub fn top_levels_string(bids: &[(u64, u64)], asks: &[(u64, u64)]) -> String {
+    let mut parts = vec![];
+    for i in 0..bids.len().max(asks.len()) {
+        if let Some((price, qty)) = bids.get(i) {
+            parts.push(format!("{}:{}", price, qty));
+        }
+        if let Some((price, qty)) = asks.get(i) {
+            parts.push(format!("{}:{}", price, qty));
+        }
+    }
+    parts.join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a_reference_values() {
+        assert_eq!(Fnv1a::default().finish(), 0xcbf2_9ce4_8422_2325);
+
+        let mut hasher = Fnv1a::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        let mut hasher = Fnv1a::default();
+        hasher.write(b"foobar");
+        assert_eq!(hasher.finish(), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn test_crc32_reference_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_top_levels_string_interleaves_sides() {
+        let bids = [(100, 5), (99, 3)];
+        let asks = [(101, 2)];
+        assert_eq!(top_levels_string(&bids, &asks), "100:5:101:2:99:3");
+        assert_eq!(top_levels_string(&[], &asks), "101:2");
+    }
+}
@@ -1,8 +1,9 @@
-use crate::data::book_event::{BookEvent, MatchEvent};
-use crate::data::orders::resting_orders::{OrderId, RestingOrder};
+use crate::data::book_event::{BookEvent, MatchEvent, ReplenishEvent, SelfTradeEvent};
+use crate::data::order_types::StpMode;
+use crate::data::orders::resting_orders::{AccountId, OrderId, RestingOrder};
+use crate::data::price_level::PriceLevel;
 use crate::orderbook::util::book_side::BookSide;
 use crate::orderbook::util::side::Side;
-use chrono::Utc;
 use rustc_hash::FxHashMap;
 use slab::Slab;
 
@@ -13,6 +14,10 @@ pub struct MatchIter<'a, OrderSide: Side> {
     order_id: u64,
     remaining: u32,
     price_limit: Option<OrderSide::Key>,
+    account_id: AccountId,
+    stp_mode: Option<StpMode>, // Self-trade prevention, None allows self-trades
+    ts: i64,                   // Engine time stamped on every event
+    pending: Option<BookEvent>, // Event queued behind the last match (iceberg replenish)
 }
 
 impl<'a, OrderSide: Side> MatchIter<'a, OrderSide> {
@@ -23,6 +28,7 @@ impl<'a, OrderSide: Side> MatchIter<'a, OrderSide> {
         order_id: u64,
         remaining: u32,
         price_limit: Option<OrderSide::Key>,
+        ts: i64,
     ) -> Self {
         Self {
             side,
@@ -31,9 +37,21 @@ impl<'a, OrderSide: Side> MatchIter<'a, OrderSide> {
             order_id,
             remaining,
             price_limit,
+            account_id: 0,
+            stp_mode: None,
+            ts,
+            pending: None,
         }
     }
 
+    /// Prevent the taker from trading against resting orders of `account_id`
+    #[inline]
+    pub fn with_stp(mut self, account_id: AccountId, stp_mode: Option<StpMode>) -> Self {
+        self.account_id = account_id;
+        self.stp_mode = stp_mode;
+        self
+    }
+
     #[inline(always)]
     pub fn remaining(&self) -> u32 {
         self.remaining
@@ -44,6 +62,10 @@ impl<'a, OrderSide: Side> Iterator for MatchIter<'a, OrderSide> {
     type Item = BookEvent;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.pending.take() {
+            return Some(event);
+        }
+
         if self.remaining == 0 {
             return None;
         }
@@ -63,42 +85,72 @@ impl<'a, OrderSide: Side> Iterator for MatchIter<'a, OrderSide> {
         debug_assert!(level.head.is_some());
         let slab_index = level.head?;
 
-        let qty = self.orders[slab_index].qty;
-
-        let traded = self.remaining.min(qty);
-        self.orders[slab_index].qty -= traded;
-        self.remaining -= traded;
-
         let order_id = self.orders[slab_index].order_id;
+        let ts = self.ts;
 
-        // If fully filled
-        if self.orders[slab_index].qty == 0 {
-            let prev = self.orders[slab_index].prev;
-            let next = self.orders[slab_index].next;
-
-            // Connect prev to next before deleting
-            if let Some(prev_index) = prev {
-                self.orders[prev_index].next = next;
-            } else {
-                // We are deleting the head
-                level.head = next;
+        // Maker and taker share an owner -> apply self-trade prevention instead of trading
+        if let Some(mode) = self.stp_mode
+            && self.orders[slab_index].account_id == self.account_id
+        {
+            let maker = &mut self.orders[slab_index];
+            let (maker_qty, taker_qty) = match mode {
+                StpMode::CancelNewest => (0, self.remaining),
+                StpMode::CancelOldest => (maker.total_qty(), 0),
+                StpMode::CancelBoth => (maker.total_qty(), self.remaining),
+                StpMode::DecrementAndCancel => {
+                    let qty = self.remaining.min(maker.total_qty());
+                    (qty, qty)
+                }
+            };
+
+            // Reserve goes first, so cancelling or decrementing an iceberg never replenishes it
+            let from_reserve = maker_qty.min(maker.hidden_qty);
+            level.hidden_qty -= from_reserve as u64;
+            maker.hidden_qty -= from_reserve;
+            let consumed = maker_qty - from_reserve;
+            self.remaining -= taker_qty;
+
+            if consumed > 0 {
+                self.pending = consume_head(
+                    level,
+                    self.orders,
+                    self.order_map,
+                    slab_index,
+                    consumed,
+                    best_price.0,
+                    ts,
+                );
             }
 
-            // Connect next to prev before deleting
-            if let Some(next_index) = next {
-                self.orders[next_index].prev = prev;
-            } else {
-                // We are deleting the tail
-                level.tail = prev;
+            if level.head.is_none() {
+                debug_assert_eq!(level.total_orders, 0);
+                entry.remove();
             }
 
-            level.total_orders -= 1;
-
-            // Remove from slab + map
-            self.orders.remove(slab_index);
-            self.order_map.remove(&order_id);
+            return Some(BookEvent::SelfTrade(SelfTradeEvent {
+                maker: order_id,
+                taker: self.order_id,
+                account_id: self.account_id,
+                mode,
+                maker_qty,
+                taker_qty,
+                ts,
+            }));
         }
 
+        let traded = self.remaining.min(self.orders[slab_index].qty);
+        self.remaining -= traded;
+
+        self.pending = consume_head(
+            level,
+            self.orders,
+            self.order_map,
+            slab_index,
+            traded,
+            best_price.0,
+            ts,
+        );
+
         // If price level empty -> remove it
         if level.head.is_none() {
             debug_assert_eq!(level.total_orders, 0);
@@ -110,7 +162,94 @@ impl<'a, OrderSide: Side> Iterator for MatchIter<'a, OrderSide> {
             taker: self.order_id,
             price: best_price.0,
             qty: traded,
-            ts: Utc::now().timestamp_micros(),
+            ts,
         }))
     }
 }
+
+/// Take `qty` off the head order of `level`
+///
+/// A filled head is removed from the level, slab and map. An iceberg with reserve left is
+/// refilled and moved to the tail instead, returning the replenish event.
+fn consume_head(
+    level: &mut PriceLevel,
+    orders: &mut Slab<RestingOrder>,
+    order_map: &mut FxHashMap<OrderId, usize>,
+    slab_index: usize,
+    qty: u32,
+    price: u64,
+    ts: i64,
+) -> Option<BookEvent> {
+    orders[slab_index].qty -= qty;
+    level.total_qty -= qty as u64;
+
+    let order_id = orders[slab_index].order_id;
+    let mut replenish = None;
+
+    // Visible slice filled but reserve left -> refill and lose time priority
+    if orders[slab_index].qty == 0 && orders[slab_index].hidden_qty > 0 {
+        let order = &mut orders[slab_index];
+        let refill = order
+            .display_qty
+            .map_or(order.hidden_qty, |display| display.min(order.hidden_qty));
+        order.qty = refill;
+        order.hidden_qty -= refill;
+        order.ts = ts;
+        level.total_qty += refill as u64;
+        level.hidden_qty -= refill as u64;
+
+        // Matching always consumes the head, so move head to the tail
+        if level.tail != Some(slab_index) {
+            let next = orders[slab_index].next;
+            level.head = next;
+            if let Some(next_index) = next {
+                orders[next_index].prev = None;
+            }
+
+            if let Some(tail) = level.tail {
+                orders[tail].next = Some(slab_index);
+            }
+            orders[slab_index].prev = level.tail;
+            orders[slab_index].next = None;
+            level.tail = Some(slab_index);
+        }
+
+        replenish = Some(BookEvent::Replenish(ReplenishEvent {
+            order_id,
+            price,
+            qty: refill,
+            hidden_qty: orders[slab_index].hidden_qty,
+            ts,
+        }));
+    }
+
+    // If fully filled
+    if orders[slab_index].qty == 0 {
+        let prev = orders[slab_index].prev;
+        let next = orders[slab_index].next;
+
+        // Connect prev to next before deleting
+        if let Some(prev_index) = prev {
+            orders[prev_index].next = next;
+        } else {
+            // We are deleting the head
+            level.head = next;
+        }
+
+        // Connect next to prev before deleting
+        if let Some(next_index) = next {
+            orders[next_index].prev = prev;
+        } else {
+            // We are deleting the tail
+            level.tail = prev;
+        }
+
+        level.total_orders -= 1;
+
+        // Remove from slab + map
+        orders.remove(slab_index);
+        order_map.remove(&order_id);
+    }
+
+    replenish
+}
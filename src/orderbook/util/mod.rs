@@ -1,4 +1,5 @@
 pub mod book_side;
+pub mod checksum;
 pub mod match_iter;
 pub mod price_key;
 pub mod side;
@@ -11,6 +11,9 @@ pub trait Side {
     fn compare_price(best: &Self::Key, limit: &Self::Key) -> bool {
         best > limit
     }
+
+    /// Price of the `bucket` wide depth bucket holding `price`, rounded away from the spread
+    fn bucket_price(price: u64, bucket: u64) -> u64;
 }
 
 pub struct Bids;
@@ -26,6 +29,11 @@ impl Side for Bids {
     fn key_to_price(key: Self::Key) -> PriceKey {
         key.0
     }
+
+    #[inline]
+    fn bucket_price(price: u64, bucket: u64) -> u64 {
+        price - price % bucket
+    }
 }
 
 pub struct Asks;
@@ -41,4 +49,9 @@ impl Side for Asks {
     fn key_to_price(key: Self::Key) -> PriceKey {
         key
     }
+
+    #[inline]
+    fn bucket_price(price: u64, bucket: u64) -> u64 {
+        price.div_ceil(bucket).saturating_mul(bucket)
+    }
 }
@@ -0,0 +1,151 @@
+//! Little-endian primitives shared by the binary file formats
+
+use crate::data::order_types::IncomingSide;
+use std::io::{self, Read};
+
+/// Append-only byte buffer
+#[derive(Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    #[inline]
+    pub fn put_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    #[inline]
+    pub fn put_u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[inline]
+    pub fn put_u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[inline]
+    pub fn put_u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[inline]
+    pub fn put_i64(&mut self, value: i64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[inline]
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Presence byte (0/1) followed by the value, zero when absent
+    #[inline]
+    pub fn put_opt_u64(&mut self, value: Option<u64>) {
+        self.put_u8(value.is_some() as u8);
+        self.put_u64(value.unwrap_or_default());
+    }
+
+    #[inline]
+    pub fn put_side(&mut self, side: &IncomingSide) {
+        self.put_u8(side.clone() as u8);
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads primitives back, any short read is an UnexpectedEof error
+pub struct Decoder<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0u8; N];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    #[inline]
+    pub fn get_u8(&mut self) -> io::Result<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    #[inline]
+    pub fn get_u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    #[inline]
+    pub fn get_u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    #[inline]
+    pub fn get_u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    #[inline]
+    pub fn get_i64(&mut self) -> io::Result<i64> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+
+    pub fn get_bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        self.array()
+    }
+
+    pub fn get_opt_u64(&mut self) -> io::Result<Option<u64>> {
+        let present = self.get_bool()?;
+        let value = self.get_u64()?;
+        Ok(present.then_some(value))
+    }
+
+    pub fn get_side(&mut self) -> io::Result<IncomingSide> {
+        match self.get_u8()? {
+            0 => Ok(IncomingSide::Buy),
+            1 => Ok(IncomingSide::Sell),
+            other => Err(invalid_data(format!("Invalid side byte: {}", other))),
+        }
+    }
+
+    pub fn get_bool(&mut self) -> io::Result<bool> {
+        match self.get_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(invalid_data(format!("Invalid flag byte: {}", other))),
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+pub fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,4 @@
+pub mod codec;
+pub mod journal;
+pub mod order_codec;
+pub mod snapshot_file;
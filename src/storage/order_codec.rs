@@ -0,0 +1,380 @@
+//! Fixed-width binary encoding of one `IncomingOrder`
+//!
+//! Every input is `ORDER_RECORD_LEN` bytes, all integers little-endian:
+//!
+//! | offset | size | field                                                              |
+//! |--------|------|--------------------------------------------------------------------|
+//! | 0      | 1    | kind: 1 limit, 2 market, 3 iceberg, 4 stop, 5 modify, 6 cancel, 7 invalid, 8 time |
+//! | 1      | 1    | side: 0 buy, 1 sell                                                |
+//! | 2      | 1    | limit: post-only (0 none, 1 reject, 2 slide), invalid: reject reason |
+//! | 3      | 1    | limit: TIF (0 GTC, 1 IOC, 2 FOK, 3 DAY, 4 GTD), stop/invalid: 1 if `aux`/order id is set |
+//! | 4      | 4    | qty (iceberg: display qty)                                         |
+//! | 8      | 8    | order id                                                           |
+//! | 16     | 8    | price (stop: trigger price)                                        |
+//! | 24     | 8    | aux: GTD expiry, iceberg total qty, stop limit price or TIME timestamp |
+//! | 32     | 8    | account id                                                         |
+//! | 40     | 4    | symbol, 0 for TIME                                                 |
+//! | 44     | 4    | reserved, zero                                                     |
+//!
+//! Fields a kind does not use are zero.
+
+use crate::data::book_event::RejectReason;
+use crate::data::order_types::{IncomingOrder, IncomingSide, PostOnlyMode, TimeInForce};
+use crate::data::orders::inbound_orders::{
+    IncomingCancelOrder, IncomingIcebergOrder, IncomingInvalidOrder, IncomingLimitOrder,
+    IncomingMarketOrder, IncomingModifyOrder, IncomingStopOrder, IncomingTimeUpdate,
+};
+use crate::storage::codec::{Decoder, Encoder, invalid_data};
+use std::io;
+
+pub const ORDER_RECORD_LEN: usize = 48;
+
+const KIND_LIMIT: u8 = 1;
+const KIND_MARKET: u8 = 2;
+const KIND_ICEBERG: u8 = 3;
+const KIND_STOP: u8 = 4;
+const KIND_MODIFY: u8 = 5;
+const KIND_CANCEL: u8 = 6;
+const KIND_INVALID: u8 = 7;
+const KIND_TIME: u8 = 8;
+
+/// Raw record fields before they are given meaning by `kind`
+#[derive(Default)]
+struct Record {
+    kind: u8,
+    side: u8,
+    flags: u8,
+    mode: u8,
+    qty: u32,
+    order_id: u64,
+    price: u64,
+    aux: u64,
+    account_id: u64,
+    symbol: u32,
+}
+
+pub fn encode_order(order: &IncomingOrder) -> [u8; ORDER_RECORD_LEN] {
+    let record = match order {
+        IncomingOrder::InboundLimit(order) => {
+            let (mode, expires_at) = match order.time_in_force {
+                TimeInForce::Gtc => (0, 0),
+                TimeInForce::Ioc => (1, 0),
+                TimeInForce::Fok => (2, 0),
+                TimeInForce::Day => (3, 0),
+                TimeInForce::Gtd(expires_at) => (4, expires_at as u64),
+            };
+            Record {
+                kind: KIND_LIMIT,
+                side: order.side.clone() as u8,
+                flags: match order.post_only {
+                    None => 0,
+                    Some(PostOnlyMode::Reject) => 1,
+                    Some(PostOnlyMode::Slide) => 2,
+                },
+                mode,
+                qty: order.qty,
+                order_id: order.order_id,
+                price: order.price,
+                aux: expires_at,
+                account_id: order.account_id,
+                symbol: order.symbol,
+            }
+        }
+        IncomingOrder::InboundMarket(order) => Record {
+            kind: KIND_MARKET,
+            side: order.side.clone() as u8,
+            qty: order.qty,
+            order_id: order.order_id,
+            account_id: order.account_id,
+            symbol: order.symbol,
+            ..Record::default()
+        },
+        IncomingOrder::InboundIceberg(order) => Record {
+            kind: KIND_ICEBERG,
+            side: order.side.clone() as u8,
+            qty: order.display_qty,
+            order_id: order.order_id,
+            price: order.price,
+            aux: order.total_qty as u64,
+            account_id: order.account_id,
+            symbol: order.symbol,
+            ..Record::default()
+        },
+        IncomingOrder::InboundStop(order) => Record {
+            kind: KIND_STOP,
+            side: order.side.clone() as u8,
+            mode: order.limit_price.is_some() as u8,
+            qty: order.qty,
+            order_id: order.order_id,
+            price: order.trigger_price,
+            aux: order.limit_price.unwrap_or_default(),
+            account_id: order.account_id,
+            symbol: order.symbol,
+            ..Record::default()
+        },
+        IncomingOrder::InboundModify(order) => Record {
+            kind: KIND_MODIFY,
+            qty: order.qty,
+            order_id: order.order_id,
+            price: order.price,
+            symbol: order.symbol,
+            ..Record::default()
+        },
+        IncomingOrder::InboundCancel(order) => Record {
+            kind: KIND_CANCEL,
+            order_id: order.order_id,
+            symbol: order.symbol,
+            ..Record::default()
+        },
+        IncomingOrder::InboundInvalid(order) => Record {
+            kind: KIND_INVALID,
+            flags: reason_code(order.reason),
+            mode: order.order_id.is_some() as u8,
+            order_id: order.order_id.unwrap_or_default(),
+            symbol: order.symbol,
+            ..Record::default()
+        },
+        IncomingOrder::InboundTime(time) => Record {
+            kind: KIND_TIME,
+            aux: time.ts as u64,
+            ..Record::default()
+        },
+    };
+
+    let mut enc = Encoder::default();
+    enc.put_u8(record.kind);
+    enc.put_u8(record.side);
+    enc.put_u8(record.flags);
+    enc.put_u8(record.mode);
+    enc.put_u32(record.qty);
+    enc.put_u64(record.order_id);
+    enc.put_u64(record.price);
+    enc.put_u64(record.aux);
+    enc.put_u64(record.account_id);
+    enc.put_u32(record.symbol);
+    enc.put_u32(0);
+
+    let mut bytes = [0u8; ORDER_RECORD_LEN];
+    bytes.copy_from_slice(enc.as_bytes());
+    bytes
+}
+
+pub fn decode_order(bytes: &[u8; ORDER_RECORD_LEN]) -> io::Result<IncomingOrder> {
+    let mut dec = Decoder::new(&bytes[..]);
+    let record = Record {
+        kind: dec.get_u8()?,
+        side: dec.get_u8()?,
+        flags: dec.get_u8()?,
+        mode: dec.get_u8()?,
+        qty: dec.get_u32()?,
+        order_id: dec.get_u64()?,
+        price: dec.get_u64()?,
+        aux: dec.get_u64()?,
+        account_id: dec.get_u64()?,
+        symbol: dec.get_u32()?,
+    };
+    if dec.get_u32()? != 0 {
+        return Err(invalid_data("Reserved order record bytes set".to_string()));
+    }
+
+    let side = || match record.side {
+        0 => Ok(IncomingSide::Buy),
+        1 => Ok(IncomingSide::Sell),
+        other => Err(invalid_data(format!("Invalid side byte: {}", other))),
+    };
+
+    let order = match record.kind {
+        KIND_LIMIT => IncomingOrder::InboundLimit(IncomingLimitOrder {
+            order_id: record.order_id,
+            price: record.price,
+            qty: record.qty,
+            side: side()?,
+            account_id: record.account_id,
+            post_only: match record.flags {
+                0 => None,
+                1 => Some(PostOnlyMode::Reject),
+                2 => Some(PostOnlyMode::Slide),
+                other => return Err(invalid_data(format!("Invalid post-only byte: {}", other))),
+            },
+            time_in_force: match record.mode {
+                0 => TimeInForce::Gtc,
+                1 => TimeInForce::Ioc,
+                2 => TimeInForce::Fok,
+                3 => TimeInForce::Day,
+                4 => TimeInForce::Gtd(record.aux as i64),
+                other => return Err(invalid_data(format!("Invalid TIF byte: {}", other))),
+            },
+            symbol: record.symbol,
+        }),
+        KIND_MARKET => IncomingOrder::InboundMarket(IncomingMarketOrder {
+            order_id: record.order_id,
+            qty: record.qty,
+            side: side()?,
+            account_id: record.account_id,
+            symbol: record.symbol,
+        }),
+        KIND_ICEBERG => IncomingOrder::InboundIceberg(IncomingIcebergOrder {
+            order_id: record.order_id,
+            price: record.price,
+            display_qty: record.qty,
+            total_qty: u32::try_from(record.aux).map_err(|e| invalid_data(e.to_string()))?,
+            side: side()?,
+            account_id: record.account_id,
+            symbol: record.symbol,
+        }),
+        KIND_STOP => IncomingOrder::InboundStop(IncomingStopOrder {
+            order_id: record.order_id,
+            trigger_price: record.price,
+            limit_price: (record.mode != 0).then_some(record.aux),
+            qty: record.qty,
+            side: side()?,
+            account_id: record.account_id,
+            symbol: record.symbol,
+        }),
+        KIND_MODIFY => IncomingOrder::InboundModify(IncomingModifyOrder {
+            order_id: record.order_id,
+            price: record.price,
+            qty: record.qty,
+            symbol: record.symbol,
+        }),
+        KIND_CANCEL => IncomingOrder::InboundCancel(IncomingCancelOrder {
+            order_id: record.order_id,
+            symbol: record.symbol,
+        }),
+        KIND_INVALID => IncomingOrder::InboundInvalid(IncomingInvalidOrder {
+            order_id: (record.mode != 0).then_some(record.order_id),
+            reason: reason_from_code(record.flags)?,
+            symbol: record.symbol,
+        }),
+        KIND_TIME => IncomingOrder::InboundTime(IncomingTimeUpdate {
+            ts: record.aux as i64,
+        }),
+        other => return Err(invalid_data(format!("Unknown order kind: {}", other))),
+    };
+
+    Ok(order)
+}
+
+fn reason_code(reason: RejectReason) -> u8 {
+    match reason {
+        RejectReason::UnknownOrderId => 0,
+        RejectReason::DuplicateOrderId => 1,
+        RejectReason::ZeroQuantity => 2,
+        RejectReason::ZeroPrice => 3,
+        RejectReason::InvalidSide => 4,
+        RejectReason::NoLiquidity => 5,
+        RejectReason::PostOnlyWouldCross => 6,
+        RejectReason::ExpiredOnArrival => 7,
+        RejectReason::Malformed => 8,
+        RejectReason::UnknownSymbol => 9,
+        RejectReason::PriceNotOnTick => 10,
+        RejectReason::QtyNotOnLot => 11,
+        RejectReason::QtyBelowMin => 12,
+        RejectReason::QtyAboveMax => 13,
+        RejectReason::PriceBelowMin => 14,
+        RejectReason::PriceAboveMax => 15,
+        RejectReason::PriceOutsideBand => 16,
+        RejectReason::PostOnlyImmediateTif => 17,
+    }
+}
+
+fn reason_from_code(code: u8) -> io::Result<RejectReason> {
+    Ok(match code {
+        0 => RejectReason::UnknownOrderId,
+        1 => RejectReason::DuplicateOrderId,
+        2 => RejectReason::ZeroQuantity,
+        3 => RejectReason::ZeroPrice,
+        4 => RejectReason::InvalidSide,
+        5 => RejectReason::NoLiquidity,
+        6 => RejectReason::PostOnlyWouldCross,
+        7 => RejectReason::ExpiredOnArrival,
+        8 => RejectReason::Malformed,
+        9 => RejectReason::UnknownSymbol,
+        10 => RejectReason::PriceNotOnTick,
+        11 => RejectReason::QtyNotOnLot,
+        12 => RejectReason::QtyBelowMin,
+        13 => RejectReason::QtyAboveMax,
+        14 => RejectReason::PriceBelowMin,
+        15 => RejectReason::PriceAboveMax,
+        16 => RejectReason::PriceOutsideBand,
+        17 => RejectReason::PostOnlyImmediateTif,
+        other => return Err(invalid_data(format!("Unknown reject reason: {}", other))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_kind_round_trips() {
+        let orders = vec![
+            IncomingOrder::InboundLimit(IncomingLimitOrder {
+                order_id: 1,
+                price: 100,
+                qty: 5,
+                side: IncomingSide::Sell,
+                account_id: 9,
+                post_only: Some(PostOnlyMode::Slide),
+                time_in_force: TimeInForce::Gtd(-5),
+                symbol: 7,
+            }),
+            IncomingOrder::InboundMarket(IncomingMarketOrder {
+                order_id: 2,
+                qty: 7,
+                side: IncomingSide::Buy,
+                account_id: 0,
+                symbol: 14,
+            }),
+            IncomingOrder::InboundIceberg(IncomingIcebergOrder {
+                order_id: 3,
+                price: 101,
+                display_qty: 2,
+                total_qty: 10,
+                side: IncomingSide::Sell,
+                account_id: 1,
+                symbol: 0,
+            }),
+            IncomingOrder::InboundStop(IncomingStopOrder {
+                order_id: 4,
+                trigger_price: 99,
+                limit_price: Some(0),
+                qty: 3,
+                side: IncomingSide::Buy,
+                account_id: 2,
+                symbol: 7,
+            }),
+            IncomingOrder::InboundModify(IncomingModifyOrder {
+                order_id: 5,
+                price: 98,
+                qty: 0,
+                symbol: 14,
+            }),
+            IncomingOrder::InboundCancel(IncomingCancelOrder {
+                order_id: 6,
+                symbol: 0,
+            }),
+            IncomingOrder::InboundInvalid(IncomingInvalidOrder {
+                order_id: None,
+                reason: RejectReason::Malformed,
+                symbol: 7,
+            }),
+            IncomingOrder::InboundTime(IncomingTimeUpdate { ts: 1_000 }),
+        ];
+
+        for order in orders {
+            let decoded = decode_order(&encode_order(&order)).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", order));
+        }
+    }
+
+    #[test]
+    fn test_unknown_kind_is_rejected() {
+        let mut bytes = encode_order(&IncomingOrder::InboundCancel(IncomingCancelOrder {
+            order_id: 1,
+            symbol: 0,
+        }));
+        bytes[0] = 42;
+        assert!(decode_order(&bytes).is_err());
+    }
+}
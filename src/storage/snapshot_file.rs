@@ -0,0 +1,380 @@
+//! Versioned binary save/restore of the full engine state
+//!
+//! Layout (all integers little-endian, see `codec`):
+//!
+//! - magic `MEST`, format version (u16)
+//! - counters: seq (u64), input seq (u64), engine time (i64)
+//! - markets (u32 count) in symbol order, each with:
+//!   - symbol (u32) and last trade price (opt u64)
+//!   - book checksum version (u32) and checksum (u64) of the saved book
+//!   - resting orders (u64 count), bids then asks, best level first, FIFO within a level:
+//!     order id (u64), side (u8), price (u64), qty (u32), hidden qty (u32),
+//!     display qty (opt u64), expiry (opt i64 as u64), account id (u64), post-only (u8),
+//!     ts (i64)
+//!   - pending stops (u64 count) in release order: order id (u64), side (u8), trigger (u64),
+//!     limit price (opt u64), qty (u32), account id (u64)
+//! - session order ids (u64 count), sorted
+//! - CRC-32 (u32) of everything before it
+//!
+//! Optional values are a presence byte followed by the value (zero when absent).
+
+use crate::data::orders::inbound_orders::IncomingStopOrder;
+use crate::data::orders::resting_orders::RestingOrder;
+use crate::engine::market::MarketState;
+use crate::engine::matching_engine::{Engine, EngineCounters};
+use crate::orderbook::util::checksum::{CHECKSUM_VERSION, crc32};
+use crate::storage::codec::{Decoder, Encoder, invalid_data};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+pub const STATE_MAGIC: [u8; 4] = *b"MEST";
+pub const STATE_FORMAT_VERSION: u16 = 3;
+
+/// Encode the full engine state
+pub fn encode_state(engine: &Engine) -> Vec<u8> {
+    let mut enc = Encoder::default();
+    enc.put_bytes(&STATE_MAGIC);
+    enc.put_u16(STATE_FORMAT_VERSION);
+
+    let counters = engine.counters();
+    enc.put_u64(counters.seq);
+    enc.put_u64(counters.input_seq);
+    enc.put_i64(counters.now);
+
+    enc.put_u32(engine.markets().count() as u32);
+    for (symbol, market) in engine.markets() {
+        enc.put_u32(symbol);
+        enc.put_opt_u64(market.last_trade_price());
+
+        let book = market.get_book();
+        enc.put_u32(CHECKSUM_VERSION);
+        enc.put_u64(book.checksum());
+
+        enc.put_u64(book.len() as u64);
+        for order in book.fifo_orders() {
+            enc.put_u64(order.order_id);
+            enc.put_side(&order.side);
+            enc.put_u64(order.price);
+            enc.put_u32(order.qty);
+            enc.put_u32(order.hidden_qty);
+            enc.put_opt_u64(order.display_qty.map(u64::from));
+            enc.put_opt_u64(order.expires_at.map(|expires_at| expires_at as u64));
+            enc.put_u64(order.account_id);
+            enc.put_u8(order.post_only as u8);
+            enc.put_i64(order.ts);
+        }
+
+        let stops = market.get_stops();
+        enc.put_u64(stops.len() as u64);
+        for stop in stops.iter() {
+            enc.put_u64(stop.order_id);
+            enc.put_side(&stop.side);
+            enc.put_u64(stop.trigger_price);
+            enc.put_opt_u64(stop.limit_price);
+            enc.put_u32(stop.qty);
+            enc.put_u64(stop.account_id);
+        }
+    }
+
+    let seen_ids = engine.seen_ids();
+    enc.put_u64(seen_ids.len() as u64);
+    for order_id in seen_ids {
+        enc.put_u64(order_id);
+    }
+
+    let crc = crc32(enc.as_bytes());
+    enc.put_u32(crc);
+    enc.into_bytes()
+}
+
+/// Rebuild an engine from `encode_state` output
+///
+/// Fails on a bad magic, unknown version, CRC mismatch, truncated data, or if the restored
+/// book does not reproduce the saved checksum.
+pub fn decode_state(bytes: &[u8], capacity: usize) -> io::Result<Engine> {
+    let Some((payload, crc)) = bytes.split_last_chunk::<4>() else {
+        return Err(invalid_data("State file too short".to_string()));
+    };
+    if crc32(payload) != u32::from_le_bytes(*crc) {
+        return Err(invalid_data("State file CRC mismatch".to_string()));
+    }
+
+    let mut dec = Decoder::new(payload);
+    if dec.get_bytes::<4>()? != STATE_MAGIC {
+        return Err(invalid_data("Not an engine state file".to_string()));
+    }
+    let version = dec.get_u16()?;
+    if version != STATE_FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "Unsupported state file version: {}",
+            version
+        )));
+    }
+
+    let counters = EngineCounters {
+        seq: dec.get_u64()?,
+        input_seq: dec.get_u64()?,
+        now: dec.get_i64()?,
+    };
+
+    let mut markets = vec![];
+    let mut checksums = vec![];
+    for _ in 0..dec.get_u32()? {
+        let symbol = dec.get_u32()?;
+        if markets
+            .last()
+            .is_some_and(|last: &MarketState| last.symbol >= symbol)
+        {
+            return Err(invalid_data(format!(
+                "Market {} out of order in state file",
+                symbol
+            )));
+        }
+        let last_trade_price = dec.get_opt_u64()?;
+        checksums.push((symbol, dec.get_u32()?, dec.get_u64()?));
+
+        let count = dec.get_u64()?;
+        let mut orders = Vec::with_capacity(count.min(capacity as u64) as usize);
+        for _ in 0..count {
+            let order_id = dec.get_u64()?;
+            let side = dec.get_side()?;
+            let price = dec.get_u64()?;
+            let qty = dec.get_u32()?;
+            let hidden_qty = dec.get_u32()?;
+            let display_qty = dec
+                .get_opt_u64()?
+                .map(|display| u32::try_from(display).map_err(|e| invalid_data(e.to_string())))
+                .transpose()?;
+            let expires_at = dec.get_opt_u64()?.map(|expires_at| expires_at as i64);
+
+            orders.push(RestingOrder {
+                order_id,
+                price,
+                qty,
+                side,
+                account_id: dec.get_u64()?,
+                display_qty,
+                hidden_qty,
+                expires_at,
+                post_only: dec.get_bool()?,
+                prev: None,
+                next: None,
+                ts: dec.get_i64()?,
+            });
+        }
+
+        let count = dec.get_u64()?;
+        let mut stops = vec![];
+        for _ in 0..count {
+            stops.push(IncomingStopOrder {
+                order_id: dec.get_u64()?,
+                side: dec.get_side()?,
+                trigger_price: dec.get_u64()?,
+                limit_price: dec.get_opt_u64()?,
+                qty: dec.get_u32()?,
+                account_id: dec.get_u64()?,
+                symbol,
+            });
+        }
+
+        markets.push(MarketState {
+            symbol,
+            last_trade_price,
+            orders,
+            stops,
+        });
+    }
+    if markets.is_empty() {
+        return Err(invalid_data("State file has no markets".to_string()));
+    }
+
+    let count = dec.get_u64()?;
+    let mut seen_ids = vec![];
+    for _ in 0..count {
+        seen_ids.push(dec.get_u64()?);
+    }
+
+    if !dec.into_inner().is_empty() {
+        return Err(invalid_data("Trailing data in state file".to_string()));
+    }
+
+    let engine = Engine::restore(capacity, counters, markets, seen_ids);
+
+    for (symbol, checksum_version, checksum) in checksums {
+        let market = engine.market(symbol).expect("Restored above");
+        // Only comparable if this build computes the checksum the same way
+        if checksum_version == CHECKSUM_VERSION && market.get_book().checksum() != checksum {
+            return Err(invalid_data(format!(
+                "Restored book of symbol {} does not match the saved checksum",
+                symbol
+            )));
+        }
+    }
+
+    Ok(engine)
+}
+
+pub fn save_state(engine: &Engine, path: &str) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&encode_state(engine))?;
+    writer.flush()
+}
+
+pub fn load_state(path: &str, capacity: usize) -> io::Result<Engine> {
+    let mut bytes = vec![];
+    BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+    decode_state(&bytes, capacity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::book_event::BookEvent;
+    use crate::data::order_types::{IncomingOrder, IncomingSide, TimeInForce};
+    use crate::data::orders::inbound_orders::{
+        IncomingIcebergOrder, IncomingLimitOrder, IncomingMarketOrder,
+    };
+
+    fn limit(id: u64, price: u64, qty: u32, side: IncomingSide, tif: TimeInForce) -> IncomingOrder {
+        IncomingOrder::InboundLimit(IncomingLimitOrder {
+            order_id: id,
+            price,
+            qty,
+            side,
+            account_id: id % 3,
+            post_only: None,
+            time_in_force: tif,
+            symbol: 0,
+        })
+    }
+
+    fn market(id: u64, qty: u32, side: IncomingSide) -> IncomingOrder {
+        IncomingOrder::InboundMarket(IncomingMarketOrder {
+            order_id: id,
+            qty,
+            side,
+            account_id: 0,
+            symbol: 0,
+        })
+    }
+
+    fn build_engine() -> Engine {
+        let mut engine = Engine::default();
+
+        engine.submit(limit(1, 100, 5, IncomingSide::Buy, TimeInForce::Gtc));
+        engine.submit(limit(2, 100, 3, IncomingSide::Buy, TimeInForce::Gtd(50)));
+        engine.submit(limit(3, 99, 7, IncomingSide::Buy, TimeInForce::Gtc));
+        engine.submit(limit(4, 103, 4, IncomingSide::Sell, TimeInForce::Gtc));
+        engine.submit(IncomingOrder::InboundIceberg(IncomingIcebergOrder {
+            order_id: 5,
+            price: 102,
+            display_qty: 2,
+            total_qty: 9,
+            side: IncomingSide::Sell,
+            account_id: 1,
+            symbol: 0,
+        }));
+        engine.submit(IncomingOrder::InboundStop(IncomingStopOrder {
+            order_id: 6,
+            trigger_price: 102,
+            limit_price: Some(104),
+            qty: 6,
+            side: IncomingSide::Buy,
+            account_id: 2,
+            symbol: 0,
+        }));
+        engine.submit(market(7, 3, IncomingSide::Sell));
+        engine
+    }
+
+    /// Inputs run against both engines after the restore
+    fn follow_up() -> Vec<IncomingOrder> {
+        vec![
+            market(8, 4, IncomingSide::Buy),
+            limit(9, 100, 2, IncomingSide::Sell, TimeInForce::Gtc),
+            limit(10, 101, 1, IncomingSide::Buy, TimeInForce::Gtc),
+        ]
+    }
+
+    fn describe(event: &BookEvent) -> String {
+        match event {
+            BookEvent::Match(fill) => format!(
+                "MATCH {} {} {} {} {}",
+                fill.maker, fill.taker, fill.price, fill.qty, fill.ts
+            ),
+            BookEvent::Insert(insert) => format!("INSERT {} {}", insert.order_id, insert.ts),
+            BookEvent::Replenish(replenish) => format!("REPLENISH {}", replenish.order_id),
+            BookEvent::Trigger(trigger) => format!("TRIGGER {}", trigger.order_id),
+            BookEvent::Expire(expire) => format!("EXPIRE {}", expire.order_id),
+            _ => "OTHER".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_restore_matches_original() {
+        let mut original = build_engine();
+        let bytes = encode_state(&original);
+        let mut restored = decode_state(&bytes, 1024).unwrap();
+
+        assert_eq!(
+            restored.get_book().checksum(),
+            original.get_book().checksum()
+        );
+        assert_eq!(restored.counters(), original.counters());
+        assert_eq!(restored.get_stops().len(), 1);
+        assert_eq!(encode_state(&restored), bytes);
+
+        // Matching after the restore is identical, including stops and iceberg refills
+        for (left, right) in follow_up().into_iter().zip(follow_up()) {
+            let expected: Vec<(u64, String)> = original
+                .submit(left)
+                .iter()
+                .map(|entry| (entry.seq, describe(&entry.event)))
+                .collect();
+            let actual: Vec<(u64, String)> = restored
+                .submit(right)
+                .iter()
+                .map(|entry| (entry.seq, describe(&entry.event)))
+                .collect();
+            assert!(!expected.is_empty());
+            assert_eq!(actual, expected);
+        }
+        assert_eq!(
+            restored.get_book().checksum(),
+            original.get_book().checksum()
+        );
+    }
+
+    #[test]
+    fn test_corrupt_state_is_rejected() {
+        let mut bytes = encode_state(&build_engine());
+        bytes[20] ^= 1;
+        assert!(decode_state(&bytes, 1024).is_err());
+
+        assert!(decode_state(&bytes[..3], 1024).is_err());
+    }
+
+    #[test]
+    fn test_restore_every_market() {
+        let mut engine = Engine::with_symbols(16, [4, 9]);
+        for (id, symbol) in [(1, 4), (2, 9), (3, 9)] {
+            let mut order = limit(id, 100 + id, 5, IncomingSide::Sell, TimeInForce::Gtc);
+            if let IncomingOrder::InboundLimit(limit) = &mut order {
+                limit.symbol = symbol;
+            }
+            engine.submit(order);
+        }
+
+        let bytes = encode_state(&engine);
+        let restored = decode_state(&bytes, 16).unwrap();
+        assert_eq!(encode_state(&restored), bytes);
+        for ((left, original), (right, restored)) in engine.markets().zip(restored.markets()) {
+            assert_eq!(left, right);
+            assert_eq!(
+                restored.get_book().checksum(),
+                original.get_book().checksum()
+            );
+        }
+        assert_eq!(restored.market(9).unwrap().get_book().len(), 2);
+    }
+}